@@ -1,226 +1,1937 @@
 use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{Display, Formatter},
     fs,
     io::{self, BufRead, Write},
+    path::Path,
+    process::Command,
     str::FromStr,
-    thread,
-    time::Duration,
+    sync::{mpsc, Arc},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
-use chrono::{SecondsFormat, Utc};
-use lettre::{transport::smtp::authentication::Credentials, SmtpTransport, Transport};
+use anyhow::{Error, Result};
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use clap::ArgEnum;
+use lettre::Transport;
+use serde::Deserialize;
 
 use crate::infra::*;
 
-pub fn test_sync_config(sync_config: &mut SyncConfig) -> Result<bool> {
+pub fn test_email_config(
+    sync_config: &mut EmailConfig,
+    timeout: Duration,
+    secrets: &Secrets,
+) -> Result<bool> {
     println!("Testing sync config...");
-    let password = sync_config.get_password()?;
-    if password.is_none() {
+    let auth = get_mail_auth(sync_config, secrets)?;
+    if auth.is_none() {
         println!("Failed. Password missing.");
         return Ok(false);
     }
 
-    println!("Sending a test mail...");
-    let password = password.unwrap();
+    match check_email_config(sync_config, &auth.unwrap(), timeout) {
+        Ok(()) => {
+            println!("Success.");
+            Ok(true)
+        }
+        Err(e) => {
+            println!("Failed. {}", e);
+            Ok(false)
+        }
+    }
+}
+
+/// Step of checking an email config, to tell which one failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmailCheckStep {
+    Config,
+    SmtpAuth,
+    SmtpSend,
+    ImapLogin,
+    Mailbox,
+    Delivery,
+    SearchCapability,
+}
+
+impl Display for EmailCheckStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            EmailCheckStep::Config => "config check",
+            EmailCheckStep::SmtpAuth => "SMTP auth",
+            EmailCheckStep::SmtpSend => "SMTP send",
+            EmailCheckStep::ImapLogin => "IMAP login",
+            EmailCheckStep::Mailbox => "mailbox preparation",
+            EmailCheckStep::Delivery => "test mail delivery",
+            EmailCheckStep::SearchCapability => "IMAP search",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct EmailCheckError {
+    pub step: EmailCheckStep,
+    pub reason: String,
+}
+
+impl EmailCheckError {
+    fn new(step: EmailCheckStep, reason: impl Display) -> EmailCheckError {
+        EmailCheckError {
+            step,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl Display for EmailCheckError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Step {} failed. Error: {}", self.step, self.reason)
+    }
+}
+
+impl std::error::Error for EmailCheckError {}
+
+/// Send a test mail and poll INBOX for it with exponential backoff until `timeout`, finding out
+/// whether IMAP searching by subject works as well. The test mail is deleted once found. With
+/// pinned certificates, which SMTP cannot check, the test mail is put into INBOX by IMAP instead.
+fn check_email_config(
+    sync_config: &mut EmailConfig,
+    auth: &MailAuth,
+    timeout: Duration,
+) -> StdResult<(), EmailCheckError> {
+    use EmailCheckStep::*;
+
+    // a config the test mail cannot even be made of fails before anything is sent
     let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
     let subject = format!("[wordmem][test][{}]", now);
-    let message = lettre::Message::builder()
-        .from(sync_config.email.parse().unwrap())
-        .to(sync_config.email.parse().unwrap())
-        .subject(&subject)
-        .body(String::new())?;
-    let mailer = match sync_config.smtp_encryption {
-        Encryption::SslTls => SmtpTransport::relay(&sync_config.smtp_server_host)?,
-        Encryption::StartTls => SmtpTransport::starttls_relay(&sync_config.smtp_server_host)?,
-    }
-    .credentials(Credentials::new(
-        sync_config.email.clone(),
-        password.clone(),
-    ))
-    .port(sync_config.smtp_server_port)
-    .build();
-    if let Err(e) = mailer.send(&message) {
-        println!("Failed. Error: {}", e);
-        return Ok(false);
+    let message = parse_email_address(&sync_config.email)
+        .and_then(|address| {
+            Ok(lettre::Message::builder()
+                .from(address.clone())
+                .to(address)
+                .subject(&subject)
+                .body(String::new())?)
+        })
+        .map_err(|e| EmailCheckError::new(Config, e))?;
+    let use_smtp = !sync_config.has_pins();
+    if !use_smtp && sync_config.get_sync_mailbox().eq_ignore_ascii_case("INBOX") {
+        return Err(EmailCheckError::new(
+            Config,
+            "Sync mails cannot be sent by SMTP with pinned certificates. Set a sync mailbox other than INBOX.",
+        ));
     }
 
-    println!("Wait 10 seconds before checking email...");
-    thread::sleep(Duration::from_secs(10));
+    if use_smtp {
+        println!("Sending a test mail...");
+        let mailer = build_smtp_transport(sync_config, auth)
+            .map_err(|e| EmailCheckError::new(SmtpAuth, e))?;
+        match mailer.test_connection() {
+            Ok(true) => (),
+            Ok(false) => return Err(EmailCheckError::new(SmtpAuth, "connection lost")),
+            Err(e) => return Err(EmailCheckError::new(SmtpAuth, e)),
+        }
+        mailer
+            .send(&message)
+            .map_err(|e| EmailCheckError::new(SmtpSend, e))?;
+    }
 
     println!("Reading the mail just sent...");
-    let mut client =
-        imap::ClientBuilder::new(&sync_config.imap_server_host, sync_config.imap_server_port);
-    let client = match sync_config.imap_encryption {
-        Encryption::SslTls => &mut client,
-        Encryption::StartTls => client.starttls(),
-    }
-    .native_tls()?;
-    let imap_session = client.login(&sync_config.email, &password);
-    if let Err((e, _c)) = imap_session {
-        println!("Failed. Error: {}", e);
-        return Ok(false);
+    let (mut imap_session, server_id) = connect_imap_identified(sync_config, auth)
+        .map_err(|e| EmailCheckError::new(ImapLogin, e))?;
+    if let Some(server_id) = server_id {
+        let fields: Vec<_> = server_id
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        println!("IMAP server identified as: {}", fields.join(", "));
     }
+    println!("Preparing mailbox {}...", sync_config.get_sync_mailbox());
+    ensure_mailbox(&mut imap_session, sync_config.get_sync_mailbox())
+        .map_err(|e| EmailCheckError::new(Mailbox, e))?;
+    if !use_smtp {
+        imap_session
+            .append("INBOX", &message.formatted())
+            .finish()
+            .map_err(|e| EmailCheckError::new(Delivery, e))?;
+    }
+
+    let started = Instant::now();
+    let mut delay = Duration::from_secs(1);
+    let mut scan = TestMailScan::default();
+    let (uid, workaround_imap_search) = loop {
+        let mailbox = imap_session
+            .select("INBOX")
+            .map_err(|e| EmailCheckError::new(Mailbox, e))?;
+        scan.start(mailbox.uid_validity);
+        if let Some(found) = find_test_mail(&mut imap_session, &subject, &mut scan)? {
+            break found;
+        }
 
-    let mut imap_session = imap_session.unwrap();
-    let caps = imap_session.capabilities()?;
-    if caps.has_str("ID") {
-        imap_session.run_command_and_check_ok(IMAP_ID_COMMAND)?;
+        let elapsed = started.elapsed();
+        if elapsed >= timeout {
+            return Err(EmailCheckError::new(
+                Delivery,
+                format!(
+                    "Test mail not found in INBOX within {} seconds.",
+                    timeout.as_secs()
+                ),
+            ));
+        }
+        let wait = delay.min(timeout - elapsed);
+        println!(
+            "Test mail not arrived yet. Check again in {:.1} seconds...",
+            wait.as_secs_f32()
+        );
+        thread::sleep(wait);
+        delay *= 2;
+    };
+    sync_config.workaround_imap_search = Some(workaround_imap_search);
+
+    // a test mail left behind is harmless, so failing to delete it is only reported
+    if let Err(e) = delete_by_uids(&mut imap_session, &uid.to_string()) {
+        println!("Failed to delete the test mail. Error: {}", e);
     }
 
-    imap_session.select("INBOX")?;
-    let seq_list = imap_session.search(format!("SUBJECT {}", subject));
-    if let Ok(seq_list) = seq_list {
-        if !seq_list.is_empty() {
-            sync_config.workaround_imap_search = Some(false);
+    Ok(())
+}
 
-            println!("Success.");
-            return Ok(true);
+/// Parse the email address of a config, which sync mails are sent from and to.
+fn parse_email_address(email: &str) -> Result<lettre::message::Mailbox> {
+    email
+        .parse()
+        .map_err(|e| Error::msg(format!("Invalid email address {}: {}", email, e)))
+}
+
+/// How far mails of INBOX have been scanned for the test mail, so that each poll only fetches
+/// mails arrived since the one before.
+#[derive(Debug, Default)]
+struct TestMailScan {
+    uid_validity: Option<u32>,
+    /// highest UID scanned, if any
+    scanned_uid: Option<u32>,
+}
+
+impl TestMailScan {
+    /// Start a poll of the mailbox just selected. UIDs scanned are forgotten if they are no longer
+    /// valid.
+    fn start(&mut self, uid_validity: Option<u32>) {
+        if self.uid_validity != uid_validity || uid_validity.is_none() {
+            self.uid_validity = uid_validity;
+            self.scanned_uid = None;
         }
     }
+}
 
-    let seq_list = imap_session.search("ALL")?;
-    if seq_list.is_empty() {
-        println!("Failed. Test mail not found in INBOX.");
-        return Ok(false);
+/// Find the test mail in the selected mailbox, by SEARCH SUBJECT or else by scanning the subjects
+/// of mails not scanned before. Returns its UID, which stays the same when other mails are
+/// expunged meanwhile, unlike its sequence number, and whether scanning was needed.
+fn find_test_mail(
+    imap_session: &mut ImapSession,
+    subject: &str,
+    scan: &mut TestMailScan,
+) -> StdResult<Option<(u32, bool)>, EmailCheckError> {
+    let search_subject = |imap_session: &mut ImapSession| {
+        imap_session
+            .uid_search(format!("SUBJECT {}", subject))
+            .ok()
+            .and_then(|uid_list| uid_list.into_iter().max())
+    };
+    if let Some(uid) = search_subject(imap_session) {
+        return Ok(Some((uid, false)));
     }
 
-    let mut seq_list: Vec<_> = seq_list.iter().collect();
-    seq_list.sort();
-    seq_list.reverse();
-    for chunk in seq_list.chunks(50) {
-        let seqset = chunk
+    let search_error = |e: imap::Error| EmailCheckError::new(EmailCheckStep::SearchCapability, e);
+    // "n:*" always matches the newest mail, even if its UID is below n
+    let query = match scan.scanned_uid {
+        Some(scanned_uid) => format!("UID {}:*", scanned_uid + 1),
+        None => "ALL".to_string(),
+    };
+    let mut uid_list: Vec<_> = imap_session
+        .uid_search(query)
+        .map_err(search_error)?
+        .into_iter()
+        .filter(|uid| {
+            scan.scanned_uid
+                .map_or(true, |scanned_uid| *uid > scanned_uid)
+        })
+        .collect();
+    uid_list.sort_unstable();
+    uid_list.reverse();
+    for chunk in uid_list.chunks(50) {
+        let uid_set = chunk
             .iter()
             .map(|s| s.to_string())
             .collect::<Vec<_>>()
             .join(",");
-        let message_list = imap_session.fetch(seqset, "BODY.PEEK[]")?;
-        if message_list.iter().any(|m| {
-            let message = mail_parser::Message::parse(m.body().unwrap()).unwrap();
-            message.get_subject().unwrap() == subject
-        }) {
-            sync_config.workaround_imap_search = Some(true);
+        let message_list = imap_session
+            .uid_fetch(uid_set, "BODY.PEEK[HEADER.FIELDS (SUBJECT)]")
+            .map_err(search_error)?;
+        // mails without subject are unrelated to the test
+        let found = message_list.iter().find(|m| {
+            m.header()
+                .and_then(mail_parser::Message::parse)
+                .and_then(|message| message.get_subject().map(|s| s == subject))
+                .unwrap_or(false)
+        });
+        if let Some(uid) = found.and_then(|m| m.uid) {
+            // the mail may have arrived between the searches, so give SEARCH SUBJECT another try
+            return Ok(Some(match search_subject(imap_session) {
+                Some(uid) => (uid, false),
+                None => (uid, true),
+            }));
+        }
+    }
+    scan.scanned_uid = uid_list.first().copied().or(scan.scanned_uid);
 
-            println!("Success.");
-            return Ok(true);
+    Ok(None)
+}
+
+/// Where to get the password for signing in.
+pub enum PasswordSource {
+    Prompt,
+    /// the first line of stdin, after any other values read from it
+    Stdin,
+    /// the first line of the output of a shell command, e.g. of a password manager
+    Command(String),
+}
+
+impl PasswordSource {
+    pub fn read(&self) -> Result<String> {
+        let password = match self {
+            PasswordSource::Prompt => rpassword::prompt_password("Enter password: ")?,
+            PasswordSource::Stdin => {
+                let mut line = String::new();
+                io::stdin().read_line(&mut line)?;
+                line.trim_end_matches(&['\r', '\n'][..]).to_string()
+            }
+            PasswordSource::Command(command) => {
+                let output = if cfg!(windows) {
+                    Command::new("cmd").args(["/C", command]).output()?
+                } else {
+                    Command::new("sh").args(["-c", command]).output()?
+                };
+                if !output.status.success() {
+                    return Err(Error::msg(format!(
+                        "Password command failed: {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    )));
+                }
+
+                let stdout = String::from_utf8(output.stdout)?;
+                stdout.lines().next().unwrap_or_default().to_string()
+            }
+        };
+        // stdin at its end or a command printing nothing would store an empty password
+        if password.is_empty() && !matches!(self, PasswordSource::Prompt) {
+            return Err(Error::msg("No password was read."));
         }
+
+        Ok(password)
     }
+}
 
-    println!("Failed. Test mail not found in INBOX.");
-    Ok(false)
+pub fn read_webdav_password(
+    webdav_config: &WebDavConfig,
+    password_source: &PasswordSource,
+    secrets: &Secrets,
+) -> Result<()> {
+    let password = password_source.read()?;
+    webdav_config.set_password(secrets, &password)?;
+    Ok(())
 }
 
-pub fn read_sync_config() -> Result<SyncConfig> {
-    let stdin = io::stdin();
-    let mut lines = stdin.lock().lines();
+pub fn test_webdav_config(webdav_config: &WebDavConfig, secrets: &Secrets) -> Result<bool> {
+    println!("Testing sync config...");
+    let mut backend = WebDavBackend::new(
+        &webdav_config.webdav_url,
+        &webdav_config.webdav_username,
+        webdav_config.get_password(secrets)?,
+        None,
+    );
+    match backend.list_snapshots() {
+        Ok(snapshots) => {
+            println!("Success. {} snapshots found.", snapshots.len());
+            Ok(true)
+        }
+        Err(e) => {
+            println!("Failed. Error: {}", e);
+            Ok(false)
+        }
+    }
+}
 
-    print!("Enter IMAP server host: ");
-    io::stdout().flush()?;
-    let imap_server_host = lines.next().unwrap()?.trim().to_string();
+/// Read the sync passphrase twice from user, and store it in the secret store of the config.
+pub fn read_sync_passphrase(app_config: &AppConfig) -> Result<bool> {
+    let passphrase = rpassword::prompt_password("Enter sync passphrase: ")?;
+    if passphrase.is_empty() {
+        println!("Failed. Passphrase must not be empty.");
+        return Ok(false);
+    }
+    if rpassword::prompt_password("Enter sync passphrase again: ")? != passphrase {
+        println!("Failed. Passphrases do not match.");
+        return Ok(false);
+    }
 
-    print!("Enter IMAP server port: ");
-    io::stdout().flush()?;
-    let imap_server_port = u16::from_str(lines.next().unwrap()?.trim())?;
+    app_config.set_sync_passphrase(&passphrase)?;
+    Ok(true)
+}
 
-    print!("Enter IMAP server encryption [ssltls/starttls]: ");
-    io::stdout().flush()?;
-    let imap_encryption = Encryption::from_str(lines.next().unwrap()?.trim())?;
+#[derive(Clone, Copy, ArgEnum)]
+pub enum OAuth2Provider {
+    /// Gmail, authorized in browser
+    Google,
+    /// Outlook and Microsoft 365, authorized by device code
+    Microsoft,
+}
 
-    print!("Enter SMTP server host: ");
-    io::stdout().flush()?;
-    let smtp_server_host = lines.next().unwrap()?.trim().to_string();
+impl OAuth2Provider {
+    pub fn get_oauth2_config(&self, client_id: &str, client_secret: Option<&str>) -> OAuth2Config {
+        match self {
+            OAuth2Provider::Google => OAuth2Config::google(client_id, client_secret),
+            OAuth2Provider::Microsoft => OAuth2Config::microsoft(client_id, client_secret),
+        }
+    }
+}
 
-    print!("Enter SMTP server port: ");
-    io::stdout().flush()?;
-    let smtp_server_port = u16::from_str(lines.next().unwrap()?.trim())?;
+/// Email settings given by flags, environment variables, a file or a provider preset.
+/// Those missing are read from user.
+#[derive(Debug, Default, Deserialize)]
+pub struct EmailSigninOptions {
+    pub imap_server_host: Option<String>,
+    pub imap_server_port: Option<u16>,
+    pub imap_encryption: Option<Encryption>,
+    pub smtp_server_host: Option<String>,
+    pub smtp_server_port: Option<u16>,
+    pub smtp_encryption: Option<Encryption>,
+    pub email: Option<String>,
+    pub sync_mailbox: Option<String>,
+    pub proxy: Option<String>,
+    pub tls: Option<TlsOptions>,
+}
 
-    print!("Enter SMTP server encryption [ssltls/starttls]: ");
-    io::stdout().flush()?;
-    let smtp_encryption = Encryption::from_str(lines.next().unwrap()?.trim())?;
+impl EmailSigninOptions {
+    /// Read options from a JSON file, with the field names of the config file.
+    pub fn load_from_file(file: &str) -> Result<EmailSigninOptions> {
+        Ok(serde_json::from_str(&fs::read_to_string(file)?)?)
+    }
+
+    /// Fill in the options missing with those of `other`.
+    pub fn or(self, other: EmailSigninOptions) -> EmailSigninOptions {
+        EmailSigninOptions {
+            imap_server_host: self.imap_server_host.or(other.imap_server_host),
+            imap_server_port: self.imap_server_port.or(other.imap_server_port),
+            imap_encryption: self.imap_encryption.or(other.imap_encryption),
+            smtp_server_host: self.smtp_server_host.or(other.smtp_server_host),
+            smtp_server_port: self.smtp_server_port.or(other.smtp_server_port),
+            smtp_encryption: self.smtp_encryption.or(other.smtp_encryption),
+            email: self.email.or(other.email),
+            sync_mailbox: self.sync_mailbox.or(other.sync_mailbox),
+            proxy: self.proxy.or(other.proxy),
+            tls: self.tls.or(other.tls),
+        }
+    }
+}
+
+/// Common mail providers, whose server settings are known.
+#[derive(Clone, Copy, ArgEnum)]
+pub enum MailProvider {
+    Gmail,
+    Outlook,
+    Fastmail,
+    Icloud,
+}
+
+impl MailProvider {
+    pub fn get_signin_options(&self) -> EmailSigninOptions {
+        let (imap_host, smtp_host, smtp_port, smtp_encryption) = match self {
+            MailProvider::Gmail => ("imap.gmail.com", "smtp.gmail.com", 465, Encryption::SslTls),
+            MailProvider::Outlook => (
+                "outlook.office365.com",
+                "smtp.office365.com",
+                587,
+                Encryption::StartTls,
+            ),
+            MailProvider::Fastmail => (
+                "imap.fastmail.com",
+                "smtp.fastmail.com",
+                465,
+                Encryption::SslTls,
+            ),
+            MailProvider::Icloud => (
+                "imap.mail.me.com",
+                "smtp.mail.me.com",
+                587,
+                Encryption::StartTls,
+            ),
+        };
 
-    print!("Enter email: ");
+        EmailSigninOptions {
+            imap_server_host: Some(imap_host.to_string()),
+            imap_server_port: Some(993),
+            imap_encryption: Some(Encryption::SslTls),
+            smtp_server_host: Some(smtp_host.to_string()),
+            smtp_server_port: Some(smtp_port),
+            smtp_encryption: Some(smtp_encryption),
+            ..Default::default()
+        }
+    }
+}
+
+/// Print the prompt and read a trimmed line from user.
+fn prompt_line<I>(lines: &mut I, prompt: &str) -> Result<String>
+where
+    I: Iterator<Item = io::Result<String>>,
+{
+    print!("{}", prompt);
     io::stdout().flush()?;
-    let email = lines.next().unwrap()?.trim().to_string();
+    let line = lines
+        .next()
+        .ok_or_else(|| Error::msg("Input ended unexpectedly."))??;
+
+    Ok(line.trim().to_string())
+}
+
+/// Read email config, taking values missing from `options` from user. With `oauth2` given,
+/// user authorizes in browser instead of giving a password.
+pub fn read_email_config(
+    options: EmailSigninOptions,
+    oauth2: Option<OAuth2Config>,
+    password_source: &PasswordSource,
+    secrets: &Secrets,
+) -> Result<EmailConfig> {
+    let encryption_prompt = "encryption [ssltls/starttls/none]: ";
+    let mut sync_config = {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+
+        let imap_server_host = match options.imap_server_host {
+            Some(host) => host,
+            None => prompt_line(&mut lines, "Enter IMAP server host: ")?,
+        };
+        let imap_server_port = match options.imap_server_port {
+            Some(port) => port,
+            None => u16::from_str(&prompt_line(&mut lines, "Enter IMAP server port: ")?)?,
+        };
+        let imap_encryption = match options.imap_encryption {
+            Some(encryption) => encryption,
+            None => Encryption::from_str(&prompt_line(
+                &mut lines,
+                &format!("Enter IMAP server {}", encryption_prompt),
+            )?)?,
+        };
+
+        let smtp_server_host = match options.smtp_server_host {
+            Some(host) => host,
+            None => prompt_line(&mut lines, "Enter SMTP server host: ")?,
+        };
+        let smtp_server_port = match options.smtp_server_port {
+            Some(port) => port,
+            None => u16::from_str(&prompt_line(&mut lines, "Enter SMTP server port: ")?)?,
+        };
+        let smtp_encryption = match options.smtp_encryption {
+            Some(encryption) => encryption,
+            None => Encryption::from_str(&prompt_line(
+                &mut lines,
+                &format!("Enter SMTP server {}", encryption_prompt),
+            )?)?,
+        };
+
+        let email = match options.email {
+            Some(email) => email,
+            None => prompt_line(&mut lines, "Enter email: ")?,
+        };
+
+        let sync_mailbox = match options.sync_mailbox {
+            Some(sync_mailbox) => sync_mailbox,
+            None => prompt_line(
+                &mut lines,
+                &format!(
+                    "Enter mailbox for sync mails [{}]: ",
+                    EmailConfig::DEFAULT_SYNC_MAILBOX
+                ),
+            )?,
+        };
+        let sync_mailbox = if sync_mailbox.is_empty() {
+            EmailConfig::DEFAULT_SYNC_MAILBOX.to_string()
+        } else {
+            sync_mailbox
+        };
 
-    let sync_config = SyncConfig {
-        imap_server_host,
-        imap_server_port,
-        imap_encryption,
+        EmailConfig {
+            imap_server_host,
+            imap_server_port,
+            imap_encryption,
 
-        smtp_server_host,
-        smtp_server_port,
-        smtp_encryption,
+            smtp_server_host,
+            smtp_server_port,
+            smtp_encryption,
 
-        email,
-        workaround_imap_search: None,
+            email,
+            workaround_imap_search: None,
+            trash_mailbox: None,
+            sync_mailbox: Some(sync_mailbox),
+            sync_cursor: None,
+            imap_id: None,
+            tls: options.tls,
+            proxy: options.proxy,
+            oauth2,
+        }
+    };
+    sync_config.check_encryption()?;
+    parse_email_address(&sync_config.email)?;
+    // the proxy URL is kept without its credentials, which are put in the secret store
+    let proxy = match sync_config.proxy.as_deref() {
+        Some(url) if !url.trim().is_empty() => Some(url.parse::<ProxyConfig>()?),
+        _ => None,
     };
+    if let Some(proxy) = &proxy {
+        sync_config.proxy = Some(proxy.to_string());
+    }
 
-    let password = rpassword::prompt_password("Enter password: ")?;
-    sync_config.set_password(&password)?;
+    if let Some(oauth2_config) = &sync_config.oauth2 {
+        let token = authorize(oauth2_config)?;
+        let refresh_token = token
+            .refresh_token
+            .ok_or_else(|| Error::msg("Authorization gave no refresh token."))?;
+        sync_config.set_refresh_token(secrets, &refresh_token)?;
+        if let Some(client_secret) = &oauth2_config.client_secret {
+            sync_config.set_client_secret(secrets, client_secret)?;
+        }
+    } else {
+        let password = password_source.read()?;
+        sync_config.set_password(secrets, &password)?;
+    }
+    if let Some((user, password)) = proxy.and_then(|p| p.credentials) {
+        sync_config.set_proxy_credentials(secrets, &user, &password)?;
+    }
 
     Ok(sync_config)
 }
 
-pub fn push_data_to_email(app_config: Option<&AppConfig>) -> Result<bool> {
-    if app_config.is_none() {
-        println!("Email not signed in. Not syncing.");
+/// What has been merged from remote, kept in DB meta, so that pulls only fetch what is new.
+#[derive(Debug, Clone, Default)]
+struct MergedState {
+    device_id: String,
+    /// data time, in seconds, of the newest full snapshot merged or pushed
+    full_time: Option<i64>,
+    /// sequence numbers of the newest deltas merged, by device
+    peer_seqs: BTreeMap<String, i64>,
+    /// data time, in seconds, of the newest snapshot merged from each device, before which the
+    /// device had merged everything, since it pulls before pushing
+    peer_times: BTreeMap<String, i64>,
+    /// deltas uploaded to remote after the newest full snapshot
+    deltas_since_full: usize,
+}
+
+impl MergedState {
+    fn load(db: &Db) -> Result<MergedState> {
+        Ok(MergedState {
+            device_id: db.get_device_id().to_string(),
+            full_time: db
+                .get_meta("merged_full_time")?
+                .map(|t| t.parse())
+                .transpose()?,
+            peer_seqs: db
+                .get_meta("peer_seqs")?
+                .map(|s| serde_json::from_str(&s))
+                .transpose()?
+                .unwrap_or_default(),
+            peer_times: db
+                .get_meta("peer_sync_times")?
+                .map(|s| serde_json::from_str(&s))
+                .transpose()?
+                .unwrap_or_default(),
+            deltas_since_full: db
+                .get_meta("deltas_since_full")?
+                .map(|n| n.parse())
+                .transpose()?
+                .unwrap_or(0),
+        })
+    }
+
+    /// Tell whether the snapshot has been merged, or was pushed by this device.
+    fn is_merged(&self, snapshot: &SnapshotInfo) -> bool {
+        match &snapshot.delta {
+            Some(delta) => {
+                delta.device_id == self.device_id
+                    || self
+                        .peer_seqs
+                        .get(&delta.device_id)
+                        .map_or(false, |&seq| delta.seq <= seq)
+            }
+            None => self.full_time == Some(snapshot.data_time.timestamp()),
+        }
+    }
+
+    fn save(&self, db: &Db) -> Result<()> {
+        if let Some(full_time) = self.full_time {
+            db.set_meta("merged_full_time", &full_time.to_string())?;
+        }
+        db.set_meta("peer_seqs", &serde_json::to_string(&self.peer_seqs)?)?;
+        db.set_meta("peer_sync_times", &serde_json::to_string(&self.peer_times)?)?;
+        db.set_meta("deltas_since_full", &self.deltas_since_full.to_string())?;
+
+        Ok(())
+    }
+}
+
+/// Snapshots fetched from remote which have not been merged, oldest first.
+struct RemoteChanges {
+    snapshots: Vec<SyncData>,
+    /// deltas at remote after the newest full snapshot, counting the ones not fetched
+    deltas_since_full: usize,
+    /// whether remote has no data at all, as far as known
+    no_data: bool,
+    /// data time, in seconds, of the newest snapshot listed
+    latest_time: Option<i64>,
+    /// id of the newest snapshot listed
+    latest_id: Option<String>,
+    /// sequence number of the oldest delta skipped as corrupt, by device
+    seq_gaps: BTreeMap<String, i64>,
+}
+
+/// Fetch a snapshot, checking its payload before anything is merged from it.
+fn fetch_valid_snapshot(
+    backend: &mut dyn SyncBackend,
+    snapshot: &SnapshotInfo,
+) -> Result<SyncData> {
+    let sync_data = backend.fetch(snapshot)?;
+    sync_data.validate()?;
+
+    Ok(sync_data)
+}
+
+/// Fetch the newest full snapshot, unless merged before, and the deltas after it from other
+/// devices which have not been merged. Corrupt snapshots are skipped and reported, and kept
+/// unseen by the backend, so that the next pull tries them again.
+fn fetch_remote_changes(
+    backend: &mut dyn SyncBackend,
+    state: &MergedState,
+) -> Result<RemoteChanges> {
+    let listed = backend.list_unseen_snapshots()?;
+    let no_data = listed.is_empty() && state.full_time.is_none();
+
+    let mut snapshots = Vec::new();
+    let mut seq_gaps = BTreeMap::new();
+    let mut delta_count = 0;
+    let mut full_found = false;
+    for s in listed.iter() {
+        if s.delta.is_some() {
+            delta_count += 1;
+        }
+        if state.is_merged(s) {
+            if s.delta.is_none() {
+                full_found = true;
+                break;
+            }
+            continue;
+        }
+
+        match fetch_valid_snapshot(backend, s) {
+            Ok(sync_data) => {
+                snapshots.push(sync_data);
+                if s.delta.is_none() {
+                    full_found = true;
+                    break;
+                }
+            }
+            // a corrupt full snapshot is passed over for the one before it
+            Err(e) if e.downcast_ref::<SyncError>().is_some() => {
+                println!("Skipped. {}", e);
+                backend.keep_unseen_snapshot(s);
+                if let Some(delta) = &s.delta {
+                    seq_gaps.insert(delta.device_id.clone(), delta.seq);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    snapshots.reverse();
+
+    Ok(RemoteChanges {
+        snapshots,
+        deltas_since_full: if full_found {
+            delta_count
+        } else {
+            state.deltas_since_full + delta_count
+        },
+        no_data,
+        latest_time: listed.first().map(|s| s.data_time.timestamp()),
+        latest_id: listed.first().map(|s| s.id.clone()),
+        seq_gaps,
+    })
+}
+
+/// Read words out of snapshots, keeping the newest change of each word, and note the time of
+/// each snapshot as synced by the device which pushed it.
+fn read_snapshot_words(snapshots: Vec<SyncData>, state: &mut MergedState) -> Result<Vec<Word>> {
+    let mut words: BTreeMap<String, Word> = BTreeMap::new();
+    for sync_data in snapshots {
+        let data_time = sync_data.data_time.timestamp();
+        // full snapshots carry the DB meta, which has the device id since schema version 3
+        let has_device_id = sync_data.get_schema_version() >= 3;
+        let remote_db_file = tempfile::Builder::new().tempfile()?;
+        fs::write(remote_db_file.path(), sync_data.db_bytes)?;
+        let remote_db = Db::new(remote_db_file.path())?;
+        let device_id = match &sync_data.delta {
+            Some(delta) => Some(delta.device_id.clone()),
+            None if has_device_id => Some(remote_db.get_device_id().to_string()),
+            None => None,
+        };
+        if let Some(device_id) = device_id.filter(|id| *id != state.device_id) {
+            let time = state.peer_times.entry(device_id).or_insert(data_time);
+            *time = data_time.max(*time);
+        }
+
+        for w in remote_db.get_all_words_for_sync()? {
+            let newer = words.get(&w.name).map_or(true, |old| {
+                (w.updated_at, &w.device_id) > (old.updated_at, &old.device_id)
+            });
+            if newer {
+                words.insert(w.name.clone(), w);
+            }
+        }
+    }
+
+    Ok(words.into_values().collect())
+}
+
+/// Merge words from remote changes into local DB, and update the local DB accordingly.
+fn merge_remote_changes(local_db: &Db, changes: RemoteChanges) -> Result<MergeResult> {
+    if let Some(latest_id) = &changes.latest_id {
+        local_db.set_meta("last_remote_snapshot", latest_id)?;
+    }
+
+    let mut state = MergedState::load(local_db)?;
+    state.deltas_since_full = changes.deltas_since_full;
+    if changes.snapshots.is_empty() {
+        state.save(local_db)?;
+        return Ok(MergeResult::default());
+    }
+
+    let mut has_full = false;
+    for sync_data in changes.snapshots.iter() {
+        let kind = match &sync_data.delta {
+            Some(delta) => {
+                // deltas after a corrupt one are merged, but not noted as such, so that they are
+                // fetched again along with it until it is read or a full snapshot replaces it
+                let seq = match changes.seq_gaps.get(&delta.device_id) {
+                    Some(&gap) => delta.seq.min(gap - 1),
+                    None => delta.seq,
+                };
+                state.peer_seqs.insert(delta.device_id.clone(), seq);
+                "changes"
+            }
+            None => {
+                has_full = true;
+                state.full_time = Some(sync_data.data_time.timestamp());
+                "data"
+            }
+        };
+        println!(
+            "Merging {} from remote [{}]...",
+            kind,
+            sync_data
+                .data_time
+                .to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
+    }
+
+    let remote_words = read_snapshot_words(changes.snapshots, &mut state)?;
+    let base_time = local_db
+        .get_meta("last_sync")?
+        .map(|t| DateTime::parse_from_rfc3339(&t))
+        .transpose()?
+        .map(|t| t.with_timezone(&Utc));
+
+    // deltas say nothing about words missing from them
+    let mut local_words = local_db.get_all_words_for_sync()?;
+    if !has_full {
+        let names: BTreeSet<&str> = remote_words.iter().map(|w| w.name.as_str()).collect();
+        local_words.retain(|w| names.contains(w.name.as_str()));
+    }
+
+    let result = merge_words(
+        local_words,
+        remote_words,
+        base_time,
+        local_db.get_device_id(),
+        Utc::now(),
+    );
+    for w in result.local_updates.iter() {
+        local_db.put_synced_word(w)?;
+    }
+    state.save(local_db)?;
+
+    Ok(result)
+}
+
+fn print_merge_result(result: &MergeResult) {
+    if result.is_empty() {
+        println!("Local data and remote data are identical.");
+        return;
+    }
+
+    for (title, names) in [
+        ("Taken from remote", &result.pulled),
+        ("Changed locally only", &result.pushed),
+        ("Changed on both sides and merged", &result.merged),
+    ] {
+        if !names.is_empty() {
+            println!("{} ({}): {}", title, names.len(), names.join(", "));
+        }
+    }
+}
+
+fn get_journal_pushed_seq(local_db: &Db) -> Result<i64> {
+    Ok(local_db
+        .get_meta("journal_pushed_seq")?
+        .map(|s| s.parse())
+        .transpose()?
+        .unwrap_or(0))
+}
+
+/// Build a delta snapshot of the words journaled since the last push, or `None` if there are none.
+fn build_delta(local_db: &Db, data_time: DateTime<Utc>, seq: i64) -> Result<Option<SyncData>> {
+    let words = local_db.get_journaled_words(get_journal_pushed_seq(local_db)?)?;
+    if words.is_empty() {
+        return Ok(None);
+    }
+
+    let delta_db_file = tempfile::Builder::new().tempfile()?;
+    let delta_db = Db::new(delta_db_file.path())?;
+    for w in words.iter() {
+        delta_db.put_synced_word(w)?;
+    }
+
+    Ok(Some(SyncData {
+        data_time,
+        db_bytes: delta_db.read_bytes()?,
+        delta: Some(DeltaInfo {
+            device_id: local_db.get_device_id().to_string(),
+            seq,
+        }),
+    }))
+}
+
+/// Merge remote changes into local DB, then upload local changes as a delta, or the whole local
+/// DB as a full snapshot once `compact_after_deltas` deltas have piled up since the last one.
+/// Returns whether a snapshot was uploaded.
+pub fn push_data(
+    local_db: &Db,
+    backend: &mut dyn SyncBackend,
+    compact_after_deltas: usize,
+) -> Result<bool> {
+    println!("Pulling data from remote to merge before pushing...");
+    let changes = fetch_remote_changes(backend, &MergedState::load(local_db)?)?;
+    let fetched = !changes.snapshots.is_empty();
+    let latest_time = changes.latest_time;
+    let result = merge_remote_changes(local_db, changes)?;
+    if fetched {
+        print_merge_result(&result);
+    }
+
+    let mut state = MergedState::load(local_db)?;
+    // the new snapshot must sort after those it builds on, even if pushed within the same second
+    let now = Utc::now();
+    let mut data_time = now;
+    if let Some(t) = latest_time.max(state.full_time) {
+        if data_time.timestamp() <= t {
+            data_time = Utc.timestamp(t + 1, 0);
+        }
+    }
+    let pushed_seq = get_journal_pushed_seq(local_db)?;
+    let seq = local_db.get_journal_seq()?.max(pushed_seq);
+    let delta = if backend.supports_delta()
+        && state.full_time.is_some()
+        && state.deltas_since_full < compact_after_deltas
+    {
+        build_delta(local_db, data_time, seq)?
+    } else {
+        None
+    };
+
+    let time_text = data_time.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let uploaded = match delta {
+        Some(delta) => {
+            println!("Pushing local changes to remote [{}]...", time_text);
+            backend.upload(&delta)?;
+            true
+        }
+        None if state.full_time.is_some() && seq == pushed_seq => {
+            println!("No local changes to push.");
+            false
+        }
+        None => {
+            println!("Pushing data to remote [{}]...", time_text);
+            backend.upload(&SyncData {
+                data_time,
+                db_bytes: local_db.read_bytes()?,
+                delta: None,
+            })?;
+            state.full_time = Some(data_time.timestamp());
+            state.deltas_since_full = 0;
+            state.save(local_db)?;
+            true
+        }
+    };
+
+    local_db.set_meta("last_sync", &now.to_rfc3339())?;
+    local_db.set_meta("last_push", &now.to_rfc3339())?;
+    local_db.set_meta("journal_pushed_seq", &seq.to_string())?;
+    local_db.compact_journal(seq)?;
+
+    Ok(uploaded)
+}
+
+/// Record the time of a pull, and take it as the base of the next merge, unless there are local
+/// changes not pushed yet, which must still count as changed then.
+fn record_pull_as_sync(local_db: &Db, now: DateTime<Utc>) -> Result<()> {
+    local_db.set_meta("last_pull", &now.to_rfc3339())?;
+    if local_db
+        .get_journaled_words(get_journal_pushed_seq(local_db)?)?
+        .is_empty()
+    {
+        local_db.set_meta("last_sync", &now.to_rfc3339())?;
+    }
+
+    Ok(())
+}
+
+/// Merge remote changes not merged before into local DB.
+pub fn pull_data(local_db: &Db, backend: &mut dyn SyncBackend) -> Result<bool> {
+    println!("Pulling data from remote...");
+    let now = Utc::now();
+    let changes = fetch_remote_changes(backend, &MergedState::load(local_db)?)?;
+    if changes.no_data {
+        println!("Data not found in remote. Syncing aborted.");
+        return Ok(false);
+    }
+    if changes.snapshots.is_empty() {
+        merge_remote_changes(local_db, changes)?;
+        record_pull_as_sync(local_db, now)?;
+        println!("No new data in remote.");
+        return Ok(true);
+    }
+
+    let result = merge_remote_changes(local_db, changes)?;
+    record_pull_as_sync(local_db, now)?;
+    print_merge_result(&result);
+    if !result.pushed.is_empty() || !result.merged.is_empty() {
+        println!("Run `wordmem push` to send local changes to remote.");
+    }
+
+    Ok(true)
+}
+
+/// Save the state learned by the backend, e.g. where the newest sync mail is, to app config.
+fn save_backend_state(backend: &dyn SyncBackend) -> Result<()> {
+    save_updated_config(backend.get_updated_config())
+}
+
+fn save_updated_config(sync_config: Option<SyncConfig>) -> Result<()> {
+    if let Some(sync_config) = sync_config {
+        let conf_file = AppConfig::get_default_conf_path();
+        let mut app_config = AppConfig::load_from_file(&conf_file)?.unwrap_or_default();
+        app_config.sync = Some(sync_config);
+        app_config.save_to_file(&conf_file)?;
+    }
+
+    Ok(())
+}
+
+/// Open the sync backend of the app config, or `None` if sync is not signed in.
+fn open_configured_backend(
+    app_config: Option<&AppConfig>,
+) -> Result<Option<Box<dyn SyncBackend + '_>>> {
+    let app_config = match app_config {
+        Some(app_config) if app_config.sync.is_some() => app_config,
+        _ => {
+            println!("Sync not signed in. Not syncing.");
+            return Ok(None);
+        }
+    };
+
+    let passphrase = app_config.get_sync_passphrase()?;
+    let mut backend = open_sync_backend(
+        app_config.sync.as_ref().unwrap(),
+        passphrase,
+        &app_config.secrets,
+    )?;
+    backend.set_payload_format(app_config.get_payload_format());
+    Ok(Some(backend))
+}
+
+pub fn push_data_to_remote(app_config: Option<&AppConfig>) -> Result<bool> {
+    let backend = open_configured_backend(app_config)?;
+    if backend.is_none() {
         return Ok(false);
     }
 
-    let app_config = app_config.unwrap();
-    if app_config.sync.is_none() {
-        println!("Email not signed in. Not syncing.");
+    push_data_to_db_path(
+        &Db::get_default_db_path(),
+        app_config.unwrap(),
+        backend.unwrap().as_mut(),
+    )?;
+    Ok(true)
+}
+
+fn push_data_to_db_path(
+    db_path: &Path,
+    app_config: &AppConfig,
+    backend: &mut dyn SyncBackend,
+) -> Result<()> {
+    let db = Db::new(db_path)?;
+    let compact_after_deltas = app_config
+        .compact_after_deltas
+        .unwrap_or(AppConfig::DEFAULT_COMPACT_AFTER_DELTAS);
+    push_data(&db, backend, compact_after_deltas)?;
+    db.set_meta("pending_push", "0")?;
+    save_backend_state(backend)?;
+
+    // old snapshots are only pruned after a successful push, and failing to prune is not fatal
+    let keep = app_config
+        .keep_snapshots
+        .unwrap_or(AppConfig::DEFAULT_KEEP_SNAPSHOTS);
+    match backend.prune(keep) {
+        Ok(0) => {}
+        Ok(count) => println!("Pruned {} old snapshots.", count),
+        Err(e) => println!("Failed to prune old snapshots. Error: {}", e),
+    }
+
+    println!("Success.");
+    Ok(())
+}
+
+/// Pull and merge remote changes, reading payloads not encrypted if `allow_plaintext`, even
+/// though a sync passphrase is set.
+pub fn pull_data_from_remote(
+    app_config: Option<&AppConfig>,
+    allow_plaintext: bool,
+) -> Result<bool> {
+    let backend = open_configured_backend(app_config)?;
+    if backend.is_none() {
         return Ok(false);
     }
 
-    let now = Utc::now();
-    println!(
-        "Pushing data to sync-email [{}]...",
-        now.to_rfc3339_opts(SecondsFormat::Secs, true)
-    );
-    SyncData {
-        data_time: now,
-        db_bytes: fs::read(Db::get_default_db_path())?,
+    let mut backend = backend.unwrap();
+    backend.set_allow_plaintext(allow_plaintext);
+    let pulled = pull_data(&Db::new(Db::get_default_db_path())?, backend.as_mut())?;
+    save_backend_state(backend.as_ref())?;
+    if !pulled {
+        return Ok(false);
     }
-    .push_data(app_config.sync.as_ref())?;
 
     println!("Success.");
     Ok(true)
 }
 
-pub fn pull_data_from_email(app_config: Option<&AppConfig>) -> Result<bool> {
-    if app_config.is_none() {
-        println!("Email not signed in. Not syncing.");
+/// Print snapshots available at remote, newest first.
+pub fn list_remote_snapshots(app_config: Option<&AppConfig>) -> Result<bool> {
+    let backend = open_configured_backend(app_config)?;
+    if backend.is_none() {
         return Ok(false);
     }
 
-    let app_config = app_config.unwrap();
-    if app_config.sync.is_none() {
-        println!("Email not signed in. Not syncing.");
-        return Ok(false);
+    let snapshots = backend.unwrap().list_snapshots()?;
+    if snapshots.is_empty() {
+        println!("No snapshots found in remote.");
+        return Ok(true);
+    }
+
+    for s in snapshots.iter() {
+        let size = s
+            .size
+            .map(|size| format!("{:.1} KiB", size as f64 / 1024.0))
+            .unwrap_or_else(|| "-".to_string());
+        let kind = match &s.delta {
+            Some(delta) => format!("delta {} #{}", delta.device_id, delta.seq),
+            None => "full".to_string(),
+        };
+        println!(
+            "{}\t{}\t{}\t{}",
+            s.data_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            kind,
+            size,
+            s.id
+        );
     }
 
-    println!("Pulling data from email...");
-    let sync_data = SyncData::pull_data(app_config.sync.as_ref())?;
-    if sync_data.is_none() {
-        println!("Data not found in email. Syncing aborted.");
+    Ok(true)
+}
+
+/// Print when this device last synced, and whether it is ahead of, behind, or diverged from
+/// remote, with what to do about it.
+pub fn print_sync_status(app_config: Option<&AppConfig>) -> Result<bool> {
+    let backend = open_configured_backend(app_config)?;
+    if backend.is_none() {
         return Ok(false);
     }
-    let sync_data = sync_data.unwrap();
 
+    let db = Db::new(Db::get_default_db_path())?;
+    let format_time = |key| -> Result<String> {
+        Ok(match db.get_meta(key)? {
+            Some(t) => DateTime::parse_from_rfc3339(&t)?
+                .with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            None => "never".to_string(),
+        })
+    };
+    println!("Last push: {}", format_time("last_push")?);
+    println!("Last pull: {}", format_time("last_pull")?);
+    if let Some(id) = db.get_meta("last_remote_snapshot")? {
+        println!("Newest remote snapshot at last sync: {}", id);
+    }
+
+    let snapshots = backend.unwrap().list_snapshots()?;
+    let (local_changes, remote_changes) = count_unsynced_changes(&db, &snapshots)?;
+    let pending_push = db.get_meta("pending_push")?.as_deref() == Some("1");
     println!(
-        "Merging data from sync-mail [{}]...",
-        sync_data
-            .data_time
-            .to_rfc3339_opts(SecondsFormat::Secs, true)
+        "Local changes not pushed: {} words{}",
+        local_changes,
+        if pending_push { " (push pending)" } else { "" }
     );
-    let email_db = tempfile::Builder::new().tempfile()?;
-    fs::write(email_db.path(), sync_data.db_bytes)?;
-    let words = Db::new(email_db.path())?.get_all_words()?;
-    let local_db = Db::new(Db::get_default_db_path())?;
-    for w in words {
-        local_db.upsert_by_name(&w, true)?;
+    match snapshots.first() {
+        Some(latest) => println!(
+            "Newest remote snapshot: {} [{}]",
+            latest.id,
+            latest.data_time.to_rfc3339_opts(SecondsFormat::Secs, true)
+        ),
+        None => println!("Newest remote snapshot: none"),
     }
+    println!("Remote changes not merged: {} snapshots", remote_changes);
+    println!("{}", get_sync_recommendation(local_changes, remote_changes));
 
-    println!("Success.");
     Ok(true)
 }
+
+/// Count words changed locally and not pushed, and snapshots at remote not merged.
+fn count_unsynced_changes(db: &Db, snapshots: &[SnapshotInfo]) -> Result<(usize, usize)> {
+    let local_changes = db.get_journaled_words(get_journal_pushed_seq(db)?)?.len();
+    let state = MergedState::load(db)?;
+    let mut remote_changes = 0;
+    // snapshots before the newest full one have been taken into it
+    for s in snapshots.iter() {
+        if !state.is_merged(s) {
+            remote_changes += 1;
+        }
+        if s.delta.is_none() {
+            break;
+        }
+    }
+
+    Ok((local_changes, remote_changes))
+}
+
+fn get_sync_recommendation(local_changes: usize, remote_changes: usize) -> &'static str {
+    match (local_changes > 0, remote_changes > 0) {
+        (true, true) => {
+            "Diverged from remote. Run `wordmem push`, which merges remote changes first."
+        }
+        (true, false) => "Ahead of remote. Run `wordmem push`.",
+        (false, true) => "Behind remote. Run `wordmem pull`.",
+        (false, false) => "Up to date with remote.",
+    }
+}
+
+/// Delete snapshots at remote older than the newest `keep` full ones.
+pub fn prune_remote_snapshots(app_config: Option<&AppConfig>, keep: usize) -> Result<bool> {
+    let backend = open_configured_backend(app_config)?;
+    if backend.is_none() {
+        return Ok(false);
+    }
+
+    let count = backend.unwrap().prune(keep)?;
+    println!("Pruned {} old snapshots.", count);
+    Ok(true)
+}
+
+/// Auto sync jobs of a command, each run in its own thread. Jobs given up waiting for keep
+/// running, and may still be writing to remote or local DB until they finish.
+#[derive(Default)]
+pub struct AutoSyncJobs {
+    timed_out: Vec<JoinHandle<()>>,
+}
+
+impl AutoSyncJobs {
+    /// Run the job in another thread, giving up waiting for it after the timeout. A job given up
+    /// on keeps running, to be waited for by `wait_for_timed_out` or `finish`.
+    fn run_with_timeout<T, F>(&mut self, timeout: Duration, job: F) -> Option<Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let _ = sender.send(job());
+        });
+
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => Some(result),
+            Err(_) => {
+                self.timed_out.push(handle);
+                None
+            }
+        }
+    }
+
+    /// Wait at most the grace period for jobs timed out earlier, so that a push is not cut off in
+    /// the middle. Returns whether all of them have finished.
+    fn wait_for_timed_out(&mut self, grace: Duration) -> bool {
+        self.timed_out.retain(|job| !job.is_finished());
+        if self.timed_out.is_empty() {
+            return true;
+        }
+
+        println!("Waiting for the sync timed out before to finish...");
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(100));
+            self.timed_out.retain(|job| !job.is_finished());
+            if self.timed_out.is_empty() {
+                return true;
+            }
+        }
+
+        println!("Gave up waiting.");
+        false
+    }
+
+    /// Wait a while for jobs timed out during a command before it exits, so that a push going on
+    /// is not cut off, which is retried by the next command otherwise.
+    pub fn finish(mut self, app_config: &AppConfig) {
+        self.wait_for_timed_out(get_auto_sync_timeout(app_config));
+    }
+}
+
+fn get_auto_sync_timeout(app_config: &AppConfig) -> Duration {
+    Duration::from_secs(
+        app_config
+            .auto_sync_timeout_secs
+            .unwrap_or(AppConfig::DEFAULT_AUTO_SYNC_TIMEOUT_SECS),
+    )
+}
+
+pub fn is_auto_sync_enabled(app_config: Option<&AppConfig>) -> bool {
+    app_config
+        .map(|c| c.auto_sync.unwrap_or(false) && c.sync.is_some())
+        .unwrap_or(false)
+}
+
+/// Where auto sync jobs, each in its own thread, get the app config and the sync backend.
+trait AutoSyncSource: Send + 'static {
+    fn load_app_config(&self) -> Result<Option<AppConfig>>;
+
+    fn open_backend<'a>(
+        &self,
+        app_config: &'a AppConfig,
+    ) -> Result<Option<Box<dyn SyncBackend + 'a>>> {
+        open_configured_backend(Some(app_config))
+    }
+}
+
+/// Auto sync by the default config file, with the sync backend signed in there. Secrets are
+/// read by the handle of the command, which has the secret file unlocked.
+struct DefaultConfSource {
+    secrets: Arc<Secrets>,
+}
+
+impl AutoSyncSource for DefaultConfSource {
+    fn load_app_config(&self) -> Result<Option<AppConfig>> {
+        let app_config = AppConfig::load_from_file(&AppConfig::get_default_conf_path())?;
+        Ok(app_config.map(|app_config| AppConfig {
+            secrets: self.secrets.clone(),
+            ..app_config
+        }))
+    }
+}
+
+/// Pull before a command if auto sync is enabled, or retry the push left pending by an earlier
+/// command. Failures and timeouts are reported, and the command goes on offline.
+pub fn auto_sync_before(
+    db: &Db,
+    app_config: &AppConfig,
+    pull: bool,
+    jobs: &mut AutoSyncJobs,
+) -> Result<()> {
+    // the job may be given up waiting for, so it must not be the one asking for the secret file password
+    if let Err(e) = unlock_secret_store(&app_config.secrets) {
+        println!("Auto syncing failed. Working offline. Error: {}", e);
+        return Ok(());
+    }
+    let source = DefaultConfSource {
+        secrets: app_config.secrets.clone(),
+    };
+    auto_sync_before_from(db, get_auto_sync_timeout(app_config), pull, source, jobs)
+}
+
+fn auto_sync_before_from<S>(
+    db: &Db,
+    timeout: Duration,
+    pull: bool,
+    source: S,
+    jobs: &mut AutoSyncJobs,
+) -> Result<()>
+where
+    S: AutoSyncSource,
+{
+    if db.get_meta("pending_push")?.as_deref() == Some("1") {
+        println!("Retrying the pending push...");
+        return auto_push_from(db, timeout, source, jobs);
+    }
+    if !pull {
+        return Ok(());
+    }
+
+    println!("Auto syncing: pulling data from remote...");
+    let state = MergedState::load(db)?;
+    let fetched = jobs.run_with_timeout(timeout, move || {
+        let app_config = source.load_app_config()?.unwrap_or_default();
+        let backend = source.open_backend(&app_config)?;
+        if backend.is_none() {
+            return Ok(None);
+        }
+
+        let mut backend = backend.unwrap();
+        let changes = fetch_remote_changes(backend.as_mut(), &state)?;
+        Ok(Some((changes, backend.get_updated_config())))
+    });
+    // merging is done here rather than in the job, so that a job timed out cannot change DB later,
+    // and the state of the backend is saved only once what it has seen is merged
+    match fetched {
+        None => println!("Timed out. Working offline."),
+        Some(Err(e)) => println!("Failed. Working offline. Error: {}", e),
+        Some(Ok(None)) => {}
+        Some(Ok(Some((changes, _)))) if changes.no_data => println!("Data not found in remote."),
+        Some(Ok(Some((changes, sync_config)))) => {
+            if let Err(e) = merge_auto_pulled(db, changes, sync_config) {
+                println!("Merging failed. Working offline. Error: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn merge_auto_pulled(
+    db: &Db,
+    changes: RemoteChanges,
+    sync_config: Option<SyncConfig>,
+) -> Result<()> {
+    let now = Utc::now();
+    let fetched = !changes.snapshots.is_empty();
+    let result = merge_remote_changes(db, changes)?;
+    record_pull_as_sync(db, now)?;
+    save_updated_config(sync_config)?;
+    if fetched {
+        print_merge_result(&result);
+    }
+
+    Ok(())
+}
+
+/// Push after a command changed words. If it fails or times out, the push is left pending
+/// to be retried by the next command.
+pub fn auto_push(db: &Db, app_config: &AppConfig, jobs: &mut AutoSyncJobs) -> Result<()> {
+    if let Err(e) = unlock_secret_store(&app_config.secrets) {
+        db.set_meta("pending_push", "1")?;
+        println!(
+            "Auto syncing failed. Will retry on the next command. Error: {}",
+            e
+        );
+        return Ok(());
+    }
+    let source = DefaultConfSource {
+        secrets: app_config.secrets.clone(),
+    };
+    auto_push_from(db, get_auto_sync_timeout(app_config), source, jobs)
+}
+
+fn auto_push_from<S>(db: &Db, timeout: Duration, source: S, jobs: &mut AutoSyncJobs) -> Result<()>
+where
+    S: AutoSyncSource,
+{
+    let db_path = match db.get_path() {
+        Some(db_path) => db_path.to_path_buf(),
+        None => return Ok(()),
+    };
+
+    db.set_meta("pending_push", "1")?;
+    // a push timed out before may still be going on, and two pushes must not race
+    if !jobs.wait_for_timed_out(timeout) {
+        println!("Will retry pushing on the next command.");
+        return Ok(());
+    }
+
+    println!("Auto syncing: pushing data to remote...");
+    let pushed = jobs.run_with_timeout(timeout, move || {
+        let app_config = source.load_app_config()?.unwrap_or_default();
+        match source.open_backend(&app_config)? {
+            Some(mut backend) => push_data_to_db_path(&db_path, &app_config, backend.as_mut()),
+            None => Ok(()),
+        }
+    });
+    match pushed {
+        // the job clears the pending flag itself
+        Some(Ok(_)) => {}
+        None => println!("Timed out. Will retry on the next command."),
+        Some(Err(e)) => println!("Failed. Will retry on the next command. Error: {}", e),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod db_syncer_tests {
+    use std::{
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
+
+    use anyhow::Result;
+
+    use super::*;
+
+    /// Sync backend uploading only once let through by the gate, as over a slow network.
+    struct GatedBackend {
+        inner: DirBackend,
+        gate: Option<Arc<Mutex<mpsc::Receiver<()>>>>,
+    }
+
+    impl SyncBackend for GatedBackend {
+        fn list_snapshots(&mut self) -> Result<Vec<SnapshotInfo>> {
+            self.inner.list_snapshots()
+        }
+
+        fn fetch(&mut self, snapshot: &SnapshotInfo) -> Result<SyncData> {
+            self.inner.fetch(snapshot)
+        }
+
+        fn upload(&mut self, data: &SyncData) -> Result<()> {
+            if let Some(gate) = &self.gate {
+                gate.lock().unwrap().recv()?;
+            }
+            self.inner.upload(data)
+        }
+
+        fn delete_snapshots(&mut self, snapshots: &[&SnapshotInfo]) -> Result<()> {
+            self.inner.delete_snapshots(snapshots)
+        }
+    }
+
+    /// Auto sync to a directory, uploading once let through by the gate if any.
+    struct DirSource {
+        dir: PathBuf,
+        upload_gate: Option<Arc<Mutex<mpsc::Receiver<()>>>>,
+    }
+
+    impl AutoSyncSource for DirSource {
+        fn load_app_config(&self) -> Result<Option<AppConfig>> {
+            Ok(Some(AppConfig::default()))
+        }
+
+        fn open_backend<'a>(
+            &self,
+            _app_config: &'a AppConfig,
+        ) -> Result<Option<Box<dyn SyncBackend + 'a>>> {
+            Ok(Some(Box::new(GatedBackend {
+                inner: DirBackend::new(&self.dir, None),
+                gate: self.upload_gate.clone(),
+            })))
+        }
+    }
+
+    #[test]
+    fn test_sync_between_devices() -> Result<()> {
+        let remote_dir = tempfile::tempdir()?;
+        let mut backend = DirBackend::new(remote_dir.path(), Some("passphrase".to_string()));
+        let db_file_a = tempfile::NamedTempFile::new()?;
+        let db_file_b = tempfile::NamedTempFile::new()?;
+        let db_a = Db::new(db_file_a.path())?;
+        let db_b = Db::new(db_file_b.path())?;
+
+        // backdate the word, so that it is not changed within the same second as syncing
+        let mut hello = Word::from_name_and_meanings("hello", "a greeting");
+        hello.updated_at = hello.updated_at - chrono::Duration::hours(1);
+        hello.device_id = db_a.get_device_id().to_string();
+        db_a.put_synced_word(&hello)?;
+        push_data(&db_a, &mut backend, AppConfig::DEFAULT_COMPACT_AFTER_DELTAS)?;
+        assert!(pull_data(&db_b, &mut backend)?);
+        let hello = db_b.get_by_col("name", SqlVal::Text("hello"))?.unwrap();
+        assert!(hello.has_meanings("a greeting"));
+        // a pull without local changes is the base of the next merge
+        assert!(db_b.get_meta("last_sync")?.is_some());
+
+        db_b.del_word(hello.id.unwrap())?;
+        push_data(&db_b, &mut backend, AppConfig::DEFAULT_COMPACT_AFTER_DELTAS)?;
+        // only the deletion is pushed, as a delta after the full snapshot of device A
+        let snapshots = backend.list_snapshots()?;
+        assert_eq!(snapshots.len(), 2);
+        assert!(snapshots[0].delta.is_some());
+        pull_data(&db_a, &mut backend)?;
+        assert!(db_a.get_by_col("name", SqlVal::Text("hello"))?.is_none());
+        // device B has merged everything before its delta, so older tombstones can be purged
+        let state = MergedState::load(&db_a)?;
+        assert_eq!(
+            state.peer_times.get(db_b.get_device_id()).copied(),
+            Some(snapshots[0].data_time.timestamp())
+        );
+
+        // compacted into a full snapshot once deltas pile up
+        db_a.insert_word(&Word::from_name_and_meanings("world", "the earth"))?;
+        push_data(&db_a, &mut backend, 1)?;
+        assert!(backend.list_snapshots()?[0].delta.is_none());
+        pull_data(&db_b, &mut backend)?;
+        assert!(db_b.get_by_col("name", SqlVal::Text("world"))?.is_some());
+
+        // a corrupt snapshot is skipped rather than failing the pull
+        let corrupt = SyncData {
+            data_time: Utc::now() + chrono::Duration::hours(1),
+            db_bytes: Vec::new(),
+            delta: None,
+        };
+        fs::write(
+            remote_dir
+                .path()
+                .join(corrupt.get_snapshot_file_name(PayloadFormat::default())),
+            b"garbage",
+        )?;
+        assert!(pull_data(&db_b, &mut backend)?);
+        assert!(db_b.get_by_col("name", SqlVal::Text("world"))?.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_status() -> Result<()> {
+        let remote_dir = tempfile::tempdir()?;
+        let mut backend = DirBackend::new(remote_dir.path(), None);
+        let db_file_a = tempfile::NamedTempFile::new()?;
+        let db_file_b = tempfile::NamedTempFile::new()?;
+        let db_a = Db::new(db_file_a.path())?;
+        let db_b = Db::new(db_file_b.path())?;
+        let status = |db: &Db, backend: &mut DirBackend| -> Result<&'static str> {
+            let (local_changes, remote_changes) =
+                count_unsynced_changes(db, &backend.list_snapshots()?)?;
+            Ok(get_sync_recommendation(local_changes, remote_changes))
+        };
+
+        db_a.insert_word(&Word::from_name_and_meanings("hello", "a greeting"))?;
+        assert!(status(&db_a, &mut backend)?.starts_with("Ahead"));
+        push_data(&db_a, &mut backend, AppConfig::DEFAULT_COMPACT_AFTER_DELTAS)?;
+        assert!(status(&db_a, &mut backend)?.starts_with("Up to date"));
+        assert!(db_a.get_meta("last_pull")?.is_none());
+        assert!(status(&db_b, &mut backend)?.starts_with("Behind"));
+        pull_data(&db_b, &mut backend)?;
+        assert!(db_b.get_meta("last_pull")?.is_some());
+        assert!(status(&db_b, &mut backend)?.starts_with("Up to date"));
+
+        // a delta pushed by this device counts as merged, and one by another device only once pulled
+        db_a.insert_word(&Word::from_name_and_meanings("world", "the earth"))?;
+        push_data(&db_a, &mut backend, AppConfig::DEFAULT_COMPACT_AFTER_DELTAS)?;
+        let snapshots = backend.list_snapshots()?;
+        assert!(MergedState::load(&db_a)?.is_merged(&snapshots[0]));
+        assert!(!MergedState::load(&db_b)?.is_merged(&snapshots[0]));
+        assert!(MergedState::load(&db_b)?.is_merged(&snapshots[1]));
+
+        db_b.insert_word(&Word::from_name_and_meanings("moon", "a satellite"))?;
+        assert!(status(&db_b, &mut backend)?.starts_with("Diverged"));
+        pull_data(&db_b, &mut backend)?;
+        assert!(MergedState::load(&db_b)?.is_merged(&snapshots[0]));
+        assert!(status(&db_b, &mut backend)?.starts_with("Ahead"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skipped_delta() -> Result<()> {
+        let remote_dir = tempfile::tempdir()?;
+        let mut backend = DirBackend::new(remote_dir.path(), None);
+        let db_file_a = tempfile::NamedTempFile::new()?;
+        let db_file_b = tempfile::NamedTempFile::new()?;
+        let db_a = Db::new(db_file_a.path())?;
+        let db_b = Db::new(db_file_b.path())?;
+        let has_word = |db: &Db, name: &str| -> Result<bool> {
+            Ok(db.get_by_col("name", SqlVal::Text(name))?.is_some())
+        };
+
+        for (name, meanings) in [("hello", "a greeting"), ("world", "the earth")] {
+            db_a.insert_word(&Word::from_name_and_meanings(name, meanings))?;
+            push_data(&db_a, &mut backend, AppConfig::DEFAULT_COMPACT_AFTER_DELTAS)?;
+        }
+        pull_data(&db_b, &mut backend)?;
+        db_a.insert_word(&Word::from_name_and_meanings("moon", "a satellite"))?;
+        push_data(&db_a, &mut backend, AppConfig::DEFAULT_COMPACT_AFTER_DELTAS)?;
+        db_a.insert_word(&Word::from_name_and_meanings("sun", "a star"))?;
+        push_data(&db_a, &mut backend, AppConfig::DEFAULT_COMPACT_AFTER_DELTAS)?;
+
+        // the delta after a corrupt one is merged, but fetched again until the corrupt one is read
+        let snapshots = backend.list_snapshots()?;
+        assert!(snapshots[1].delta.is_some());
+        let corrupt_path = remote_dir.path().join(&snapshots[1].id);
+        let payload = fs::read(&corrupt_path)?;
+        fs::write(&corrupt_path, b"garbage")?;
+        pull_data(&db_b, &mut backend)?;
+        assert!(!has_word(&db_b, "moon")?);
+        assert!(has_word(&db_b, "sun")?);
+        assert!(!MergedState::load(&db_b)?.is_merged(&snapshots[0]));
+
+        fs::write(&corrupt_path, payload)?;
+        pull_data(&db_b, &mut backend)?;
+        assert!(has_word(&db_b, "moon")?);
+        assert!(MergedState::load(&db_b)?.is_merged(&snapshots[0]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_without_changes() -> Result<()> {
+        let remote_dir = tempfile::tempdir()?;
+        let mut backend = DirBackend::new(remote_dir.path(), None);
+        let db_file = tempfile::NamedTempFile::new()?;
+        let db = Db::new(db_file.path())?;
+        db.insert_word(&Word::from_name_and_meanings("hello", "a greeting"))?;
+        let push = |backend: &mut DirBackend| {
+            push_data(&db, backend, AppConfig::DEFAULT_COMPACT_AFTER_DELTAS)
+        };
+        assert!(push(&mut backend)?);
+        let pushed_seq = db.get_meta("journal_pushed_seq")?;
+
+        // the journal compacted by the push still tells that nothing changed since
+        assert!(!push(&mut backend)?);
+        assert_eq!(backend.list_snapshots()?.len(), 1);
+        assert_eq!(db.get_meta("journal_pushed_seq")?, pushed_seq);
+
+        db.insert_word(&Word::from_name_and_meanings("world", "the earth"))?;
+        assert!(push(&mut backend)?);
+        let snapshots = backend.list_snapshots()?;
+        assert_eq!(snapshots.len(), 2);
+        assert!(snapshots[0].delta.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_sync() -> Result<()> {
+        let remote_dir = tempfile::tempdir()?;
+        let source = || DirSource {
+            dir: remote_dir.path().to_path_buf(),
+            upload_gate: None,
+        };
+        let timeout = Duration::from_secs(10);
+        let mut jobs = AutoSyncJobs::default();
+        let db_file_a = tempfile::NamedTempFile::new()?;
+        let db_file_b = tempfile::NamedTempFile::new()?;
+        let db_a = Db::new(db_file_a.path())?;
+        let db_b = Db::new(db_file_b.path())?;
+        let has_word = |db: &Db, name: &str| -> Result<bool> {
+            Ok(db.get_by_col("name", SqlVal::Text(name))?.is_some())
+        };
+
+        db_a.insert_word(&Word::from_name_and_meanings("hello", "a greeting"))?;
+        auto_push_from(&db_a, timeout, source(), &mut jobs)?;
+        assert_eq!(db_a.get_meta("pending_push")?.as_deref(), Some("0"));
+        auto_sync_before_from(&db_b, timeout, true, source(), &mut jobs)?;
+        assert!(has_word(&db_b, "hello")?);
+
+        // a push timed out is left pending, and keeps going until it is done or given up on
+        db_b.insert_word(&Word::from_name_and_meanings("world", "the earth"))?;
+        let (open_gate, gate) = mpsc::channel();
+        let gated_source = DirSource {
+            dir: remote_dir.path().to_path_buf(),
+            upload_gate: Some(Arc::new(Mutex::new(gate))),
+        };
+        auto_push_from(&db_b, Duration::from_millis(10), gated_source, &mut jobs)?;
+        assert_eq!(db_b.get_meta("pending_push")?.as_deref(), Some("1"));
+        assert!(!jobs.wait_for_timed_out(Duration::ZERO));
+        open_gate.send(())?;
+        assert!(jobs.wait_for_timed_out(timeout));
+        assert_eq!(db_b.get_meta("pending_push")?.as_deref(), Some("0"));
+
+        // a push failed is retried by the next command, instead of pulling
+        db_b.insert_word(&Word::from_name_and_meanings("moon", "a satellite"))?;
+        let not_dir = tempfile::NamedTempFile::new()?;
+        let broken_source = DirSource {
+            dir: not_dir.path().join("remote"),
+            upload_gate: None,
+        };
+        auto_push_from(&db_b, timeout, broken_source, &mut jobs)?;
+        assert_eq!(db_b.get_meta("pending_push")?.as_deref(), Some("1"));
+        auto_sync_before_from(&db_b, timeout, true, source(), &mut jobs)?;
+        assert_eq!(db_b.get_meta("pending_push")?.as_deref(), Some("0"));
+
+        auto_sync_before_from(&db_a, timeout, true, source(), &mut jobs)?;
+        assert!(has_word(&db_a, "world")?);
+        assert!(has_word(&db_a, "moon")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_signin_options() -> Result<()> {
+        let options = EmailSigninOptions {
+            imap_server_port: Some(1993),
+            email: Some("someone@fastmail.com".to_string()),
+            ..Default::default()
+        }
+        .or(MailProvider::Fastmail.get_signin_options());
+        assert_eq!(
+            options.imap_server_host.as_deref(),
+            Some("imap.fastmail.com")
+        );
+        assert_eq!(options.imap_server_port, Some(1993));
+        assert_eq!(options.email.as_deref(), Some("someone@fastmail.com"));
+
+        let password = PasswordSource::Command("echo secret".to_string()).read()?;
+        assert_eq!(password, "secret");
+        assert!(PasswordSource::Command("exit 1".to_string())
+            .read()
+            .is_err());
+        assert!(PasswordSource::Command("true".to_string()).read().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_email_config() -> Result<()> {
+        for search_subject in [true, false] {
+            let server = FakeMailServer::start(search_subject)?;
+            server.add_message("INBOX", b"Subject: hello\r\n\r\nhi\r\n");
+            let mut email_config = server.get_email_config(Some(EmailConfig::DEFAULT_SYNC_MAILBOX));
+            check_email_config(&mut email_config, &server.get_auth(), Duration::ZERO)?;
+            assert_eq!(email_config.workaround_imap_search, Some(!search_subject));
+            assert_eq!(
+                server.count_messages(EmailConfig::DEFAULT_SYNC_MAILBOX),
+                Some(0)
+            );
+            // the test mail is cleaned up, and nothing else
+            assert_eq!(server.count_messages("INBOX"), Some(1));
+        }
+
+        let server = FakeMailServer::start(true)?;
+        let mut email_config = server.get_email_config(None);
+        let wrong_auth = MailAuth::from(MailLogin::Password("wrong".to_string()));
+        let error = check_email_config(&mut email_config, &wrong_auth, Duration::ZERO).unwrap_err();
+        assert_eq!(error.step, EmailCheckStep::SmtpAuth);
+        email_config.email = "not an address".to_string();
+        let error = check_email_config(&mut email_config, &wrong_auth, Duration::ZERO).unwrap_err();
+        assert_eq!(error.step, EmailCheckStep::Config);
+
+        // servers such as 163.com refuse SELECT from clients not sending IMAP ID
+        let server = FakeMailServer::start(true)?;
+        server.require_id();
+        let mut email_config = server.get_email_config(None);
+        check_email_config(&mut email_config, &server.get_auth(), Duration::ZERO)?;
+        let client_id = server.get_client_id().unwrap();
+        assert_eq!(client_id.get("name").map(|s| s.as_str()), Some("wordmem"));
+        assert_eq!(
+            client_id.get("version").map(|s| s.as_str()),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+
+        email_config.imap_id = Some(false);
+        let error =
+            check_email_config(&mut email_config, &server.get_auth(), Duration::ZERO).unwrap_err();
+        assert_eq!(error.step, EmailCheckStep::Mailbox);
+
+        // with pinned certificates, the test mail is put by IMAP, since SMTP cannot check them
+        let server = FakeMailServer::start(true)?;
+        let mut email_config = server.get_email_config(None);
+        email_config.tls = Some(TlsOptions {
+            pinned_sha256: Some(vec!["00".repeat(32)]),
+            ..Default::default()
+        });
+        let error =
+            check_email_config(&mut email_config, &server.get_auth(), Duration::ZERO).unwrap_err();
+        assert_eq!(error.step, EmailCheckStep::Config);
+        email_config.sync_mailbox = Some(EmailConfig::DEFAULT_SYNC_MAILBOX.to_string());
+        email_config.smtp_server_port = 1;
+        check_email_config(&mut email_config, &server.get_auth(), Duration::ZERO)?;
+        assert_eq!(server.count_messages("INBOX"), Some(0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_test_mail() -> Result<()> {
+        let server = FakeMailServer::start(false)?;
+        server.add_message("INBOX", b"Subject: hello\r\n\r\nhi\r\n");
+        let mut imap_session = connect_imap(&server.get_email_config(None), &server.get_auth())?;
+        let subject = "[wordmem][test][now]";
+        let mut scan = TestMailScan::default();
+        scan.start(imap_session.select("INBOX")?.uid_validity);
+        assert_eq!(find_test_mail(&mut imap_session, subject, &mut scan)?, None);
+        assert_eq!(scan.scanned_uid, Some(1));
+
+        // the next poll only scans the mail arrived since
+        server.add_message("INBOX", b"Subject: [wordmem][test][now]\r\n\r\n");
+        scan.start(imap_session.select("INBOX")?.uid_validity);
+        assert_eq!(scan.scanned_uid, Some(1));
+        assert_eq!(
+            find_test_mail(&mut imap_session, subject, &mut scan)?,
+            Some((2, true))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_by_email() -> Result<()> {
+        for search_subject in [true, false] {
+            let server = FakeMailServer::start(search_subject)?;
+            let mut email_config = server.get_email_config(Some(EmailConfig::DEFAULT_SYNC_MAILBOX));
+            email_config.workaround_imap_search = Some(!search_subject);
+            ensure_mailbox(
+                &mut connect_imap(&email_config, &server.get_auth())?,
+                EmailConfig::DEFAULT_SYNC_MAILBOX,
+            )?;
+            let mut backend_a = EmailBackend::with_auth(&email_config, server.get_auth(), None);
+            let mut backend_b = EmailBackend::with_auth(&email_config, server.get_auth(), None);
+            let db_file_a = tempfile::NamedTempFile::new()?;
+            let db_file_b = tempfile::NamedTempFile::new()?;
+            let db_a = Db::new(db_file_a.path())?;
+            let db_b = Db::new(db_file_b.path())?;
+
+            assert!(!pull_data(&db_b, &mut backend_b)?);
+
+            let put_word = |db: &Db, meanings: &str, minutes: i64| -> Result<()> {
+                let mut hello = Word::from_name_and_meanings("hello", meanings);
+                hello.updated_at = Utc::now() - chrono::Duration::minutes(minutes);
+                hello.device_id = db.get_device_id().to_string();
+                db.put_synced_word(&hello)
+            };
+            put_word(&db_a, "a greeting", 120)?;
+            push_data(
+                &db_a,
+                &mut backend_a,
+                AppConfig::DEFAULT_COMPACT_AFTER_DELTAS,
+            )?;
+            // sync mails found by SEARCH SUBJECT were once taken for none found
+            assert!(pull_data(&db_b, &mut backend_b)?);
+            assert!(db_b.get_by_col("name", SqlVal::Text("hello"))?.is_some());
+
+            // changed on both sides, and merged by the device pulling the other's change
+            put_word(&db_a, "a greeting;a salutation", 60)?;
+            put_word(&db_b, "a greeting;an exclamation", 30)?;
+            push_data(
+                &db_a,
+                &mut backend_a,
+                AppConfig::DEFAULT_COMPACT_AFTER_DELTAS,
+            )?;
+            push_data(
+                &db_b,
+                &mut backend_b,
+                AppConfig::DEFAULT_COMPACT_AFTER_DELTAS,
+            )?;
+            assert!(pull_data(&db_a, &mut backend_a)?);
+            for db in [&db_a, &db_b] {
+                let hello = db.get_by_col("name", SqlVal::Text("hello"))?.unwrap();
+                // the meanings of the later change win
+                assert!(hello.has_meanings("a greeting;an exclamation"));
+            }
+        }
+
+        Ok(())
+    }
+}
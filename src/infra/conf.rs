@@ -1,19 +1,26 @@
 use std::{
     fmt::{Display, Formatter},
     fs,
+    net::IpAddr,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::Arc,
 };
 
-use anyhow::Result;
+use anyhow::{Error, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::infra::KEYRING_SERVICE;
+use crate::infra::{
+    clear_secret, get_secret, set_secret, OAuth2Config, PayloadCompression, PayloadFormat,
+    SecretStore, Secrets,
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Encryption {
     SslTls,
     StartTls,
+    /// plaintext, only for servers on localhost such as a mail bridge or a test stand-in
+    None,
 }
 
 impl Display for Encryption {
@@ -21,6 +28,7 @@ impl Display for Encryption {
         f.write_str(match self {
             Encryption::SslTls => "ssltls",
             Encryption::StartTls => "starttls",
+            Encryption::None => "none",
         })
     }
 }
@@ -32,6 +40,7 @@ impl FromStr for Encryption {
         match s.trim().to_lowercase().as_str() {
             "ssltls" => Ok(Encryption::SslTls),
             "starttls" => Ok(Encryption::StartTls),
+            "none" => Ok(Encryption::None),
 
             _ => Err(Self::Err::msg(format!(
                 "Unrecognized encryption method: {}",
@@ -41,8 +50,99 @@ impl FromStr for Encryption {
     }
 }
 
+/// Where to sync data. The backend is named by the `backend` field in the config file, and a config
+/// without it is an email one, as written before other backends were added.
 #[derive(Debug, Serialize, Deserialize)]
-pub struct SyncConfig {
+#[serde(
+    tag = "backend",
+    rename_all = "lowercase",
+    try_from = "serde_json::Value"
+)]
+pub enum SyncConfig {
+    Email(EmailConfig),
+    WebDav(WebDavConfig),
+    Git(GitConfig),
+    Directory(DirConfig),
+}
+
+impl TryFrom<serde_json::Value> for SyncConfig {
+    type Error = serde_json::Error;
+
+    fn try_from(mut value: serde_json::Value) -> std::result::Result<Self, Self::Error> {
+        let backend = value.as_object_mut().and_then(|v| v.remove("backend"));
+        let backend = match &backend {
+            Some(backend) => backend.as_str().unwrap_or_default(),
+            None => "email",
+        };
+        Ok(match backend {
+            "email" => SyncConfig::Email(serde_json::from_value(value)?),
+            "webdav" => SyncConfig::WebDav(serde_json::from_value(value)?),
+            "git" => SyncConfig::Git(serde_json::from_value(value)?),
+            "directory" => SyncConfig::Directory(serde_json::from_value(value)?),
+            _ => {
+                return Err(serde::de::Error::custom(format!(
+                    "unknown sync backend {}, expected email, webdav, git or directory",
+                    backend
+                )))
+            }
+        })
+    }
+}
+
+impl SyncConfig {
+    /// Accounts under which the secrets of the backend are kept.
+    pub fn get_secret_accounts(&self) -> Vec<String> {
+        match self {
+            SyncConfig::Email(email_config) => vec![
+                email_config.email.clone(),
+                email_config.get_oauth2_keyring_account(),
+                email_config.get_oauth2_client_keyring_account(),
+                email_config.get_proxy_keyring_account(),
+            ],
+            SyncConfig::WebDav(webdav_config) => vec![webdav_config.get_keyring_account()],
+            SyncConfig::Git(_) | SyncConfig::Directory(_) => Vec::new(),
+        }
+    }
+
+    /// Remove the secrets of the backend from the secret store.
+    pub fn clear_secrets(&self, secrets: &Secrets) -> Result<()> {
+        for account in self.get_secret_accounts() {
+            clear_secret(secrets, &account)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DirConfig {
+    /// directory to put snapshots in, e.g. one synced by Syncthing or Dropbox, or on a USB stick
+    pub dir: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GitConfig {
+    /// URL of the remote repository, authenticated the way git itself is, e.g. by SSH keys
+    pub git_remote: String,
+    /// local clone used as working copy
+    pub git_repo: PathBuf,
+    /// branch to commit to, "main" if absent
+    pub git_branch: Option<String>,
+}
+
+impl GitConfig {
+    pub const DEFAULT_BRANCH: &'static str = "main";
+}
+
+/// Where the newest sync mail was last seen, so that pulls only look at mails after it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImapSyncCursor {
+    pub uid_validity: u32,
+    pub last_uid: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailConfig {
     pub imap_server_host: String,
     pub imap_server_port: u16,
     pub imap_encryption: Encryption,
@@ -55,35 +155,270 @@ pub struct SyncConfig {
 
     /// use SEARCH ALL to workaround SEARCH SUBJECT <subject>
     pub workaround_imap_search: Option<bool>,
+
+    /// move pruned sync mails to this mailbox, e.g. "Trash", instead of deleting them
+    pub trash_mailbox: Option<String>,
+
+    /// mailbox keeping sync mails, INBOX if absent
+    pub sync_mailbox: Option<String>,
+    pub sync_cursor: Option<ImapSyncCursor>,
+
+    /// TLS settings for self-hosted servers with their own CA or certificates
+    pub tls: Option<TlsOptions>,
+
+    /// proxy to connect to mail servers through, e.g. "socks5://host:1080" or "http://host:8080",
+    /// that in `ALL_PROXY` if absent unless the server is in `NO_PROXY`, or none if empty.
+    /// Its username and password are kept in the secret store.
+    pub proxy: Option<String>,
+
+    /// send IMAP ID after login: always if true, never if false,
+    /// or only when the server announces the ID capability if absent
+    pub imap_id: Option<bool>,
+
+    /// log in by XOAUTH2 with a refresh token kept in system keyring, instead of password
+    pub oauth2: Option<OAuth2Config>,
 }
 
-impl SyncConfig {
-    pub fn get_password(&self) -> Result<Option<String>> {
-        let password = keyring::Entry::new(KEYRING_SERVICE, &self.email).get_password();
-        if let Err(keyring::Error::NoEntry) = password {
-            return Ok(None);
-        }
+/// How mail server certificates are verified, besides by the CAs of the system.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TlsOptions {
+    /// PEM file of extra CA certificates to trust, e.g. the internal CA of a team
+    pub ca_file: Option<PathBuf>,
+    /// SHA-256 fingerprints of IMAP server certificates in hex, colons allowed. If any is given,
+    /// only certificates matching one are accepted, whoever issued them. SMTP is not used then, as
+    /// its transport cannot check them, so sync mails are put by IMAP into a sync mailbox other
+    /// than INBOX.
+    pub pinned_sha256: Option<Vec<String>>,
+    /// INSECURE: accept any certificate for any host. Only for test servers.
+    pub insecure_skip_verify: Option<bool>,
+}
 
-        Ok(Some(password.unwrap()))
+impl TlsOptions {
+    /// Get the pinned fingerprints in lowercase hex without colons.
+    pub fn get_pins(&self) -> Vec<String> {
+        self.pinned_sha256
+            .iter()
+            .flatten()
+            .map(|p| p.replace(':', "").trim().to_lowercase())
+            .collect()
     }
+}
 
-    pub fn set_password(&self, password: &str) -> Result<()> {
-        keyring::Entry::new(KEYRING_SERVICE, &self.email).set_password(password)?;
-        Ok(())
+fn is_loopback_host(host: &str) -> bool {
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map_or(false, |ip| ip.is_loopback())
+}
+
+impl EmailConfig {
+    pub const DEFAULT_SYNC_MAILBOX: &'static str = "wordmem";
+
+    pub fn get_sync_mailbox(&self) -> &str {
+        self.sync_mailbox.as_deref().unwrap_or("INBOX")
+    }
+
+    /// Tell whether server certificates are pinned, which rules out SMTP.
+    pub fn has_pins(&self) -> bool {
+        self.tls
+            .as_ref()
+            .map_or(false, |o| !o.get_pins().is_empty())
     }
 
-    pub fn clear_password(&self) -> Result<()> {
-        keyring::Entry::new(KEYRING_SERVICE, &self.email).delete_password()?;
+    /// Refuse plaintext to servers other than on localhost, where no one in between could read
+    /// the password.
+    pub fn check_encryption(&self) -> Result<()> {
+        for (role, host, encryption) in [
+            ("IMAP", &self.imap_server_host, &self.imap_encryption),
+            ("SMTP", &self.smtp_server_host, &self.smtp_encryption),
+        ] {
+            if matches!(encryption, Encryption::None) && !is_loopback_host(host) {
+                return Err(Error::msg(format!(
+                    "Encryption none is only for servers on localhost, not {} server {}.",
+                    role, host
+                )));
+            }
+        }
+
         Ok(())
     }
+
+    pub fn get_password(&self, secrets: &Secrets) -> Result<Option<String>> {
+        get_secret(secrets, &self.email)
+    }
+
+    pub fn set_password(&self, secrets: &Secrets, password: &str) -> Result<()> {
+        set_secret(secrets, &self.email, password)
+    }
+
+    fn get_oauth2_keyring_account(&self) -> String {
+        format!("oauth2:{}", self.email)
+    }
+
+    fn get_oauth2_client_keyring_account(&self) -> String {
+        format!("oauth2-client:{}", self.email)
+    }
+
+    fn get_proxy_keyring_account(&self) -> String {
+        format!("proxy:{}", self.email)
+    }
+
+    /// Get the username and password of the proxy configured, if it asks for them.
+    pub fn get_proxy_credentials(&self, secrets: &Secrets) -> Result<Option<(String, String)>> {
+        let credentials = get_secret(secrets, &self.get_proxy_keyring_account())?;
+        Ok(credentials.and_then(|c| {
+            c.split_once(':')
+                .map(|(user, password)| (user.to_string(), password.to_string()))
+        }))
+    }
+
+    pub fn set_proxy_credentials(
+        &self,
+        secrets: &Secrets,
+        user: &str,
+        password: &str,
+    ) -> Result<()> {
+        set_secret(
+            secrets,
+            &self.get_proxy_keyring_account(),
+            &format!("{}:{}", user, password),
+        )
+    }
+
+    pub fn get_client_secret(&self, secrets: &Secrets) -> Result<Option<String>> {
+        get_secret(secrets, &self.get_oauth2_client_keyring_account())
+    }
+
+    pub fn set_client_secret(&self, secrets: &Secrets, client_secret: &str) -> Result<()> {
+        set_secret(
+            secrets,
+            &self.get_oauth2_client_keyring_account(),
+            client_secret,
+        )
+    }
+
+    pub fn get_refresh_token(&self, secrets: &Secrets) -> Result<Option<String>> {
+        get_secret(secrets, &self.get_oauth2_keyring_account())
+    }
+
+    pub fn set_refresh_token(&self, secrets: &Secrets, refresh_token: &str) -> Result<()> {
+        set_secret(secrets, &self.get_oauth2_keyring_account(), refresh_token)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+pub struct WebDavConfig {
+    /// URL of the collection to put snapshots in
+    pub webdav_url: String,
+    pub webdav_username: String,
+}
+
+impl WebDavConfig {
+    fn get_keyring_account(&self) -> String {
+        format!("webdav:{}@{}", self.webdav_username, self.webdav_url)
+    }
+
+    pub fn get_password(&self, secrets: &Secrets) -> Result<Option<String>> {
+        get_secret(secrets, &self.get_keyring_account())
+    }
+
+    pub fn set_password(&self, secrets: &Secrets, password: &str) -> Result<()> {
+        set_secret(secrets, &self.get_keyring_account(), password)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct AppConfig {
     pub sync: Option<SyncConfig>,
+
+    /// days to keep tombstones of deleted words before `wordmem gc` purges them
+    pub tombstone_max_age_days: Option<u32>,
+
+    /// encrypt sync payloads with the passphrase kept in the secret store
+    pub encrypt_sync: Option<bool>,
+
+    /// where to keep secrets, system keyring if available or an encrypted file otherwise if absent
+    pub secret_store: Option<SecretStore>,
+    /// handle to the store above, shared by the auto sync jobs of a command
+    #[serde(skip)]
+    pub secrets: Arc<Secrets>,
+
+    /// compression of sync payloads: Bzip2, Gzip or Zstd, Bzip2 if absent
+    pub sync_compression: Option<PayloadCompression>,
+    /// level of the compression, its default if absent
+    pub sync_compression_level: Option<i32>,
+
+    /// full snapshots to keep at the sync backend after each push, along with deltas after them
+    pub keep_snapshots: Option<usize>,
+    /// push a full snapshot instead of a delta once this many deltas follow the last one
+    pub compact_after_deltas: Option<usize>,
+
+    /// IANA timezone name for study days, e.g. "Asia/Shanghai", the local time of the system if absent
+    pub timezone: Option<String>,
+    /// hour a study day starts at, 4 if absent, so that studying past midnight counts for the day before
+    pub day_rollover_hour: Option<u32>,
+
+    /// pull before studying and push after changes automatically
+    pub auto_sync: Option<bool>,
+    /// seconds to wait for remote when syncing automatically, before going on offline
+    pub auto_sync_timeout_secs: Option<u64>,
 }
 
 impl AppConfig {
+    pub const DEFAULT_TOMBSTONE_MAX_AGE_DAYS: u32 = 30;
+    pub const DEFAULT_KEEP_SNAPSHOTS: usize = 10;
+    pub const DEFAULT_COMPACT_AFTER_DELTAS: usize = 20;
+    pub const DEFAULT_AUTO_SYNC_TIMEOUT_SECS: u64 = 15;
+    pub const DEFAULT_DAY_ROLLOVER_HOUR: u32 = 4;
+    const SYNC_PASSPHRASE_ACCOUNT: &'static str = "sync-passphrase";
+
+    /// Get the passphrase to encrypt sync payloads with, or `None` if encryption is disabled.
+    pub fn get_sync_passphrase(&self) -> Result<Option<String>> {
+        if !self.encrypt_sync.unwrap_or(false) {
+            return Ok(None);
+        }
+
+        let passphrase = get_secret(&self.secrets, Self::SYNC_PASSPHRASE_ACCOUNT)?;
+        if passphrase.is_none() {
+            return Err(Error::msg(
+                "Sync passphrase missing. Run `wordmem signin --encrypt` to set it.",
+            ));
+        }
+
+        Ok(passphrase)
+    }
+
+    pub fn set_sync_passphrase(&self, passphrase: &str) -> Result<()> {
+        set_secret(&self.secrets, Self::SYNC_PASSPHRASE_ACCOUNT, passphrase)
+    }
+
+    pub fn clear_sync_passphrase(&self) -> Result<()> {
+        clear_secret(&self.secrets, Self::SYNC_PASSPHRASE_ACCOUNT)
+    }
+
+    /// Accounts under which all the secrets of the config are kept.
+    pub fn get_secret_accounts(&self) -> Vec<String> {
+        let mut accounts = self
+            .sync
+            .as_ref()
+            .map(|s| s.get_secret_accounts())
+            .unwrap_or_default();
+        accounts.push(Self::SYNC_PASSPHRASE_ACCOUNT.to_string());
+
+        accounts
+    }
+
+    pub fn get_payload_format(&self) -> PayloadFormat {
+        PayloadFormat {
+            compression: self
+                .sync_compression
+                .unwrap_or(PayloadFormat::default().compression),
+            level: self.sync_compression_level,
+        }
+    }
+
     pub fn get_default_conf_dir() -> PathBuf {
         let mut conf_dir = PathBuf::new();
         conf_dir.push(dirs::config_dir().unwrap());
@@ -109,7 +444,8 @@ impl AppConfig {
         }
 
         let json_text = fs::read_to_string(file)?;
-        let app_config: AppConfig = serde_json::from_str(&json_text)?;
+        let mut app_config: AppConfig = serde_json::from_str(&json_text)?;
+        app_config.secrets = Arc::new(Secrets::new(app_config.secret_store));
         Ok(Some(app_config))
     }
 
@@ -124,12 +460,51 @@ impl AppConfig {
             }
         }
 
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(file)?;
-        serde_json::to_writer(file, self)?;
+        // write to a temporary file first, so that the config is never left half-written
+        let dir = dir
+            .filter(|d| !d.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let mut tmp_file = tempfile::NamedTempFile::new_in(dir)?;
+        serde_json::to_writer(tmp_file.as_file_mut(), self)?;
+        tmp_file.persist(file)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod conf_tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_sync_config_backend() -> Result<()> {
+        let json = serde_json::to_string(&SyncConfig::Directory(DirConfig {
+            dir: PathBuf::from("snapshots"),
+        }))?;
+        assert_eq!(json, r#"{"backend":"directory","dir":"snapshots"}"#);
+        assert!(matches!(
+            serde_json::from_str(&json)?,
+            SyncConfig::Directory(DirConfig { .. })
+        ));
+
+        // email configs written before the backend was named
+        let json = r#"{"imap_server_host":"imap.example.com","imap_server_port":993,
+            "imap_encryption":"SslTls","smtp_server_host":"smtp.example.com",
+            "smtp_server_port":465,"smtp_encryption":"SslTls","email":"me@example.com"}"#;
+        assert!(matches!(
+            serde_json::from_str(json)?,
+            SyncConfig::Email(EmailConfig { .. })
+        ));
+
+        // a typo is reported by the field of the backend named
+        let error = serde_json::from_str::<SyncConfig>(
+            r#"{"backend":"webdav","webdav_ur":"https://example.com","webdav_username":"me"}"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("webdav_url"));
+        assert!(serde_json::from_str::<SyncConfig>(r#"{"backend":"ftp"}"#).is_err());
 
         Ok(())
     }
@@ -1,4 +1,9 @@
 pub const DEFAULT_DB_FILENAME: &str = "wordmem.sqlite";
 pub const KEYRING_SERVICE: &str = "wordmem.lrs";
-pub const IMAP_ID_COMMAND: &str =
-    r##"ID ("name" "rust-imap" "version" "3.0.0-alpha.6" "vendor" "rust-imap")"##;
+/// Fields identifying wordmem to IMAP servers by the ID extension (RFC 2971).
+pub const IMAP_ID_PARAMS: &[(&str, &str)] = &[
+    ("name", env!("CARGO_PKG_NAME")),
+    ("version", env!("CARGO_PKG_VERSION")),
+    ("vendor", "longrunsoul"),
+    ("support-url", env!("CARGO_PKG_REPOSITORY")),
+];
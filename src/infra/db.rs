@@ -1,31 +1,150 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
-use chrono::Utc;
+use anyhow::{Error, Result};
+use chrono::{DateTime, Utc};
 use sqlite::Connection;
 
 use crate::infra::{AppConfig, SqlVal, Word, DEFAULT_DB_FILENAME};
 
 pub struct Db {
     conn: Connection,
+    /// id of this device, stamped on every word changed through this DB
+    device_id: String,
+    /// file of the DB, or `None` for in-memory DB
+    path: Option<PathBuf>,
 }
 
+/// Schema migrations, the n-th of which upgrades the DB to `user_version` n+1.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS word (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        meanings TEXT NOT NULL,
+        period_days INTEGER NOT NULL,
+        last_visit INTEGER NOT NULL,
+        next_visit INTEGER NOT NULL
+    );",
+    "ALTER TABLE word ADD COLUMN examples TEXT NOT NULL DEFAULT '';
+    ALTER TABLE word ADD COLUMN tags TEXT NOT NULL DEFAULT '';",
+    "ALTER TABLE word ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE word ADD COLUMN device_id TEXT NOT NULL DEFAULT '';
+    UPDATE word SET updated_at = last_visit;
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );",
+    "ALTER TABLE word ADD COLUMN deleted_at INTEGER;",
+    // journal words changed by this device, so that only they are pushed;
+    // existing words are all journaled, since they may not have been pushed yet
+    "CREATE TABLE IF NOT EXISTS journal (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        changed_at INTEGER NOT NULL
+    );
+    INSERT INTO journal (name, changed_at) SELECT name, updated_at FROM word;
+    CREATE TRIGGER IF NOT EXISTS journal_word_insert AFTER INSERT ON word
+        WHEN NEW.device_id = (SELECT value FROM meta WHERE key = 'device_id')
+    BEGIN
+        INSERT INTO journal (name, changed_at) VALUES (NEW.name, NEW.updated_at);
+    END;
+    CREATE TRIGGER IF NOT EXISTS journal_word_update AFTER UPDATE OF updated_at ON word
+        WHEN NEW.device_id = (SELECT value FROM meta WHERE key = 'device_id')
+    BEGIN
+        INSERT INTO journal (name, changed_at) VALUES (NEW.name, NEW.updated_at);
+    END;",
+];
+
 impl Db {
-    fn init(&self) -> Result<()> {
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS word (
-                id INTEGER PRIMARY KEY,
-                name TEXT NOT NULL UNIQUE,
-                meanings TEXT NOT NULL,
-                period_days INTEGER NOT NULL,
-                last_visit INTEGER NOT NULL,
-                next_visit INTEGER NOT NULL
-            );",
+    fn init(&mut self) -> Result<()> {
+        let version = self.get_schema_version()?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            self.conn.execute(format!(
+                "BEGIN;
+                {}
+                PRAGMA user_version = {};
+                COMMIT;",
+                migration,
+                i + 1
+            ))?;
+        }
+
+        self.device_id = match self.get_meta("device_id")? {
+            Some(device_id) => device_id,
+            None => {
+                let device_id = hex::encode(rand::random::<[u8; 8]>());
+                self.set_meta("device_id", &device_id)?;
+                device_id
+            }
+        };
+
+        Ok(())
+    }
+
+    pub fn get_device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    pub fn get_meta(&self, key: &str) -> Result<Option<String>> {
+        let mut value = None;
+        self.conn.iterate(
+            format!(
+                "SELECT value FROM meta WHERE key = {} LIMIT 1;",
+                SqlVal::Text(key)
+            ),
+            |pairs| {
+                value = pairs[0].1.map(|v| v.to_string());
+                true
+            },
         )?;
+
+        Ok(value)
+    }
+
+    pub fn set_meta(&self, key: &str, value: &str) -> Result<()> {
+        self.conn.execute(format!(
+            "INSERT INTO meta (key, value) VALUES ({}, {})
+                ON CONFLICT(key) DO UPDATE SET value=excluded.value;",
+            SqlVal::Text(key),
+            SqlVal::Text(value)
+        ))?;
+
         Ok(())
     }
 
+    /// Get a value that changes whenever any word is changed, deletions included.
+    pub fn get_change_marker(&self) -> Result<String> {
+        let mut marker = String::new();
+        self.conn.iterate(
+            "SELECT COUNT(*), MAX(updated_at), SUM(updated_at) FROM word;",
+            |pairs| {
+                marker = pairs
+                    .iter()
+                    .map(|(_, v)| v.unwrap_or_default())
+                    .collect::<Vec<_>>()
+                    .join(":");
+                true
+            },
+        )?;
+
+        Ok(marker)
+    }
+
+    pub fn get_schema_version(&self) -> Result<i64> {
+        let mut version = 0;
+        self.conn.iterate("PRAGMA user_version;", |pairs| {
+            version = pairs[0].1.and_then(|v| v.parse().ok()).unwrap_or(0);
+            true
+        })?;
+
+        Ok(version)
+    }
+
+    /// Get the schema version DBs are migrated to by this wordmem.
+    pub fn get_latest_schema_version() -> i64 {
+        MIGRATIONS.len() as i64
+    }
+
     pub fn get_default_db_name() -> String {
         DEFAULT_DB_FILENAME.to_string()
     }
@@ -38,8 +157,10 @@ impl Db {
 
     #[allow(dead_code)]
     pub fn new_mem() -> Result<Db> {
-        let db = Db {
+        let mut db = Db {
             conn: Connection::open(":memory:")?,
+            device_id: String::new(),
+            path: None,
         };
         db.init()?;
         Ok(db)
@@ -56,17 +177,34 @@ impl Db {
             }
         }
 
-        let db = Db {
-            conn: Connection::open(file)?,
+        let mut db = Db {
+            conn: Connection::open(&file)?,
+            device_id: String::new(),
+            path: Some(file.as_ref().to_path_buf()),
         };
         db.init()?;
         Ok(db)
     }
 
+    pub fn get_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Read the bytes of the DB file, e.g. to sync it.
+    pub fn read_bytes(&self) -> Result<Vec<u8>> {
+        match &self.path {
+            Some(path) => Ok(fs::read(path)?),
+            None => Err(Error::msg("In-memory DB has no file to read.")),
+        }
+    }
+
     pub fn get_by_col(&self, col: &str, val: SqlVal) -> Result<Option<Word>> {
         let mut result = None;
         self.conn.iterate(
-            format!("SELECT * FROM word WHERE {} = {} LIMIT 1;", col, val),
+            format!(
+                "SELECT * FROM word WHERE {} = {} AND deleted_at IS NULL LIMIT 1;",
+                col, val
+            ),
             |pairs| {
                 result = Some(Word::from_sqlite_pairs(pairs));
                 true
@@ -80,15 +218,36 @@ impl Db {
         Ok(Some(word))
     }
 
+    /// Insert a word, reviving the deleted one of the same name if any.
+    /// Fails if a word of the same name exists.
     pub fn insert_word(&self, word: &Word) -> Result<()> {
+        if self.get_by_col("name", SqlVal::Text(&word.name))?.is_some() {
+            return Err(Error::msg(format!("Word already exists: {}", word.name)));
+        }
+
         self.conn.execute(format!(
-            "INSERT INTO word (name, meanings, period_days, last_visit, next_visit)
-                VALUES ({}, {}, {}, {}, {});",
+            "INSERT INTO word (name, meanings, period_days, last_visit, next_visit, examples, tags, updated_at, device_id)
+                VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {})
+            ON CONFLICT(name) DO UPDATE SET
+                meanings=excluded.meanings,
+                period_days=excluded.period_days,
+                last_visit=excluded.last_visit,
+                next_visit=excluded.next_visit,
+                examples=excluded.examples,
+                tags=excluded.tags,
+                updated_at=excluded.updated_at,
+                device_id=excluded.device_id,
+                deleted_at=NULL
+            WHERE deleted_at IS NOT NULL;",
             SqlVal::Text(&word.name),
             SqlVal::Text(&word.meanings),
             SqlVal::Integer(word.period_days as i64),
             SqlVal::Integer(word.last_visit.timestamp()),
-            SqlVal::Integer(word.next_visit.timestamp())
+            SqlVal::Integer(word.next_visit.timestamp()),
+            SqlVal::Text(&word.examples),
+            SqlVal::Text(&word.tags),
+            SqlVal::Integer(Utc::now().timestamp()),
+            SqlVal::Text(&self.device_id)
         ))?;
 
         Ok(())
@@ -102,22 +261,35 @@ impl Db {
                     meanings={},
                     period_days={},
                     last_visit={},
-                    next_visit={}
+                    next_visit={},
+                    examples={},
+                    tags={},
+                    updated_at={},
+                    device_id={}
                 WHERE id = {};",
             SqlVal::Text(&word.name),
             SqlVal::Text(&word.meanings),
             SqlVal::Integer(word.period_days as i64),
             SqlVal::Integer(word.last_visit.timestamp()),
             SqlVal::Integer(word.next_visit.timestamp()),
+            SqlVal::Text(&word.examples),
+            SqlVal::Text(&word.tags),
+            SqlVal::Integer(Utc::now().timestamp()),
+            SqlVal::Text(&self.device_id),
             SqlVal::Integer(word.id.unwrap())
         ))?;
 
         Ok(())
     }
 
+    /// Mark a word as deleted. The tombstone is kept so that the deletion can be synced.
     pub fn del_word(&self, id: i64) -> Result<()> {
+        let now = Utc::now().timestamp();
         self.conn.execute(format!(
-            "DELETE FROM word WHERE id = {};",
+            "UPDATE word SET deleted_at={}, updated_at={}, device_id={} WHERE id = {};",
+            SqlVal::Integer(now),
+            SqlVal::Integer(now),
+            SqlVal::Text(&self.device_id),
             SqlVal::Integer(id)
         ))?;
 
@@ -125,18 +297,47 @@ impl Db {
     }
 
     pub fn clear_words(&self) -> Result<()> {
-        self.conn.execute("DELETE FROM word;")?;
+        let now = Utc::now().timestamp();
+        self.conn.execute(format!(
+            "UPDATE word SET deleted_at={}, updated_at={}, device_id={} WHERE deleted_at IS NULL;",
+            SqlVal::Integer(now),
+            SqlVal::Integer(now),
+            SqlVal::Text(&self.device_id)
+        ))?;
+
         Ok(())
     }
 
-    pub fn get_one_word_to_test(&self) -> Result<Option<Word>> {
+    /// Remove tombstones of words deleted before the given time. Returns the count removed.
+    pub fn purge_tombstones(&self, deleted_before: DateTime<Utc>) -> Result<usize> {
+        let condition = format!(
+            "deleted_at IS NOT NULL AND deleted_at < {}",
+            SqlVal::Integer(deleted_before.timestamp())
+        );
+        let mut count = 0;
+        self.conn.iterate(
+            format!("SELECT COUNT(*) FROM word WHERE {};", condition),
+            |pairs| {
+                count = pairs[0].1.and_then(|v| v.parse().ok()).unwrap_or(0);
+                true
+            },
+        )?;
+        self.conn
+            .execute(format!("DELETE FROM word WHERE {};", condition))?;
+
+        Ok(count)
+    }
+
+    /// Get a word whose next visit is before `due_before`, the earliest due first.
+    pub fn get_one_word_to_test(&self, due_before: DateTime<Utc>) -> Result<Option<Word>> {
         let mut result = None;
-        let now = Utc::now();
         self.conn.iterate(
             format!(
-                // make next visit due ahead of 18 hours(64800 seconds) to ignore the offset in a day
-                "SELECT * FROM word WHERE (next_visit - 64800) <= {} ORDER BY next_visit ASC LIMIT 1;",
-                SqlVal::Integer(now.timestamp())
+                // words without meanings (e.g. imported from Kindle) cannot be tested yet
+                "SELECT * FROM word
+                    WHERE next_visit < {} AND meanings != '' AND deleted_at IS NULL
+                    ORDER BY next_visit ASC LIMIT 1;",
+                SqlVal::Integer(due_before.timestamp())
             ),
             |pairs| {
                 result = Some(Word::from_sqlite_pairs(pairs));
@@ -155,16 +356,22 @@ impl Db {
         self.conn.execute(
             format!(
                 "INSERT INTO
-                    word (name, meanings, period_days, last_visit, next_visit)
-                    VALUES ({}, {}, {}, {}, {})
+                    word (name, meanings, period_days, last_visit, next_visit, examples, tags, updated_at, device_id)
+                    VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {})
                 ON CONFLICT(name) DO UPDATE SET
-                    meanings={}",
+                    meanings=excluded.meanings,
+                    updated_at=excluded.updated_at,
+                    device_id=excluded.device_id,
+                    deleted_at=NULL",
                 SqlVal::Text(&word.name),
                 SqlVal::Text(&word.meanings),
                 SqlVal::Integer(word.period_days as i64),
                 SqlVal::Integer(word.last_visit.timestamp()),
                 SqlVal::Integer(word.next_visit.timestamp()),
-                SqlVal::Text(&word.meanings),
+                SqlVal::Text(&word.examples),
+                SqlVal::Text(&word.tags),
+                SqlVal::Integer(Utc::now().timestamp()),
+                SqlVal::Text(&self.device_id),
             ) + if update_visit_info {
                 format!(
                     ",
@@ -182,7 +389,93 @@ impl Db {
         Ok(())
     }
 
+    /// Write a word received from sync as it is, keeping its modification time and device id.
+    pub fn put_synced_word(&self, word: &Word) -> Result<()> {
+        self.conn.execute(format!(
+            "INSERT INTO
+                word (name, meanings, period_days, last_visit, next_visit, examples, tags, updated_at, device_id, deleted_at)
+                VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {})
+            ON CONFLICT(name) DO UPDATE SET
+                meanings=excluded.meanings,
+                period_days=excluded.period_days,
+                last_visit=excluded.last_visit,
+                next_visit=excluded.next_visit,
+                examples=excluded.examples,
+                tags=excluded.tags,
+                updated_at=excluded.updated_at,
+                device_id=excluded.device_id,
+                deleted_at=excluded.deleted_at;",
+            SqlVal::Text(&word.name),
+            SqlVal::Text(&word.meanings),
+            SqlVal::Integer(word.period_days as i64),
+            SqlVal::Integer(word.last_visit.timestamp()),
+            SqlVal::Integer(word.next_visit.timestamp()),
+            SqlVal::Text(&word.examples),
+            SqlVal::Text(&word.tags),
+            SqlVal::Integer(word.updated_at.timestamp()),
+            SqlVal::Text(&word.device_id),
+            match word.deleted_at {
+                Some(t) => SqlVal::Integer(t.timestamp()),
+                None => SqlVal::Null,
+            },
+        ))?;
+
+        Ok(())
+    }
+
+    /// Get the sequence number of the newest journal entry ever written, or 0 if none. It is kept
+    /// by SQLite for the AUTOINCREMENT key, so it stays after the journal is compacted.
+    pub fn get_journal_seq(&self) -> Result<i64> {
+        let mut seq = 0;
+        self.conn.iterate(
+            "SELECT seq FROM sqlite_sequence WHERE name = 'journal';",
+            |pairs| {
+                seq = pairs[0].1.and_then(|v| v.parse().ok()).unwrap_or(0);
+                true
+            },
+        )?;
+
+        Ok(seq)
+    }
+
+    /// Get words journaled after the sequence number, including tombstones.
+    pub fn get_journaled_words(&self, after_seq: i64) -> Result<Vec<Word>> {
+        let mut words = Vec::new();
+        self.conn.iterate(
+            format!(
+                "SELECT * FROM word WHERE name IN (SELECT name FROM journal WHERE seq > {});",
+                SqlVal::Integer(after_seq)
+            ),
+            |pairs| {
+                words.push(Word::from_sqlite_pairs(pairs));
+                true
+            },
+        )?;
+        words.into_iter().collect()
+    }
+
+    /// Remove journal entries up to the sequence number, once they have been pushed.
+    pub fn compact_journal(&self, up_to_seq: i64) -> Result<()> {
+        self.conn.execute(format!(
+            "DELETE FROM journal WHERE seq <= {};",
+            SqlVal::Integer(up_to_seq)
+        ))?;
+
+        Ok(())
+    }
+
     pub fn get_all_words(&self) -> Result<Vec<Word>> {
+        let mut words = Vec::new();
+        self.conn
+            .iterate("SELECT * FROM word WHERE deleted_at IS NULL", |pairs| {
+                words.push(Word::from_sqlite_pairs(pairs));
+                true
+            })?;
+        words.into_iter().collect()
+    }
+
+    /// Get all words including tombstones of deleted ones, for syncing.
+    pub fn get_all_words_for_sync(&self) -> Result<Vec<Word>> {
         let mut words = Vec::new();
         self.conn.iterate("SELECT * FROM word", |pairs| {
             words.push(Word::from_sqlite_pairs(pairs));
@@ -211,10 +504,18 @@ mod db_tests {
             period_days: 3,
             last_visit: Utc.datetime_from_str("2022-03-21 09:09:33", "%Y-%m-%d %H:%M:%S")?,
             next_visit: Utc.datetime_from_str("2022-03-22 09:09:33", "%Y-%m-%d %H:%M:%S")?,
+            examples: "e1\ne2".to_string(),
+            tags: "t1;t2".to_string(),
+            updated_at: Utc.timestamp(0, 0),
+            device_id: String::new(),
+            deleted_at: None,
         };
         db.insert_word(&word_new)?;
         let mut word = db.get_by_col("name", SqlVal::Text("name"))?.unwrap();
+        assert_eq!(word.device_id, db.get_device_id());
         word_new.id = word.id;
+        word_new.updated_at = word.updated_at;
+        word_new.device_id = word.device_id.clone();
         assert_eq!(word, word_new);
 
         // update
@@ -227,8 +528,40 @@ mod db_tests {
 
         // delete
         db.del_word(word.id.unwrap())?;
-        let word = db.get_by_col("id", SqlVal::Integer(word.id.unwrap()))?;
-        assert_eq!(word, None);
+        let deleted = db.get_by_col("id", SqlVal::Integer(word.id.unwrap()))?;
+        assert_eq!(deleted, None);
+        let tombstone = db.get_all_words_for_sync()?.pop().unwrap();
+        assert!(tombstone.deleted_at.is_some());
+
+        // revive
+        db.insert_word(&Word::from_name_and_meanings("world", "m1"))?;
+        let word = db.get_by_col("name", SqlVal::Text("world"))?.unwrap();
+        assert_eq!(word.meanings, "m1".to_string());
+        assert!(db
+            .insert_word(&Word::from_name_and_meanings("world", "m2"))
+            .is_err());
+
+        // purge
+        db.del_word(word.id.unwrap())?;
+        assert_eq!(
+            db.purge_tombstones(Utc::now() + chrono::Duration::days(1))?,
+            1
+        );
+        assert!(db.get_all_words_for_sync()?.is_empty());
+
+        // journal, of words changed by this device only
+        let seq = db.get_journal_seq()?;
+        assert!(seq > 0);
+        let mut remote = Word::from_name_and_meanings("remote", "m1");
+        remote.device_id = "other".to_string();
+        db.put_synced_word(&remote)?;
+        assert_eq!(db.get_journal_seq()?, seq);
+        db.insert_word(&Word::from_name_and_meanings("local", "m1"))?;
+        assert_eq!(db.get_journaled_words(seq)?.len(), 1);
+        let seq = db.get_journal_seq()?;
+        db.compact_journal(seq)?;
+        assert!(db.get_journaled_words(0)?.is_empty());
+        assert_eq!(db.get_journal_seq()?, seq);
 
         Ok(())
     }
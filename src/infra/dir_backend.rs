@@ -0,0 +1,152 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::infra::{PayloadFormat, SnapshotInfo, SyncBackend, SyncData};
+
+/// Sync backend keeping snapshots as files in a directory.
+pub struct DirBackend {
+    dir: PathBuf,
+    /// passphrase to encrypt payloads with, if any
+    passphrase: Option<String>,
+    /// whether payloads not encrypted are read though a passphrase is given
+    allow_plaintext: bool,
+    format: PayloadFormat,
+}
+
+impl DirBackend {
+    pub fn new<T>(dir: T, passphrase: Option<String>) -> DirBackend
+    where
+        T: AsRef<Path>,
+    {
+        DirBackend {
+            dir: dir.as_ref().to_path_buf(),
+            passphrase,
+            allow_plaintext: false,
+            format: PayloadFormat::default(),
+        }
+    }
+
+    fn get_snapshot_path(&self, id: &str) -> PathBuf {
+        let mut path = self.dir.clone();
+        path.push(id);
+
+        path
+    }
+}
+
+impl SyncBackend for DirBackend {
+    fn list_snapshots(&mut self) -> Result<Vec<SnapshotInfo>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let (data_time, delta) = match SyncData::parse_snapshot_file_name(&file_name) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            snapshots.push(SnapshotInfo {
+                id: file_name,
+                data_time,
+                size: Some(entry.metadata()?.len()),
+                delta,
+            });
+        }
+        snapshots.sort_by(|a, b| b.data_time.cmp(&a.data_time));
+
+        Ok(snapshots)
+    }
+
+    fn fetch(&mut self, snapshot: &SnapshotInfo) -> Result<SyncData> {
+        let payload = fs::read(self.get_snapshot_path(&snapshot.id))?;
+        let mut sync_data = SyncData::from_payload(
+            snapshot.data_time,
+            &payload,
+            self.passphrase.as_deref(),
+            self.allow_plaintext,
+        )?;
+        sync_data.delta = snapshot.delta.clone();
+        Ok(sync_data)
+    }
+
+    fn upload(&mut self, data: &SyncData) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let id = data.get_snapshot_file_name(self.format);
+
+        // write to a hidden file first, so that a half-written snapshot is never seen by others
+        let tmp_path = self.get_snapshot_path(&format!(".{}.tmp", id));
+        fs::write(
+            &tmp_path,
+            data.to_payload(self.format, self.passphrase.as_deref())?,
+        )?;
+        fs::rename(&tmp_path, self.get_snapshot_path(&id))?;
+
+        Ok(())
+    }
+
+    fn set_payload_format(&mut self, format: PayloadFormat) {
+        self.format = format;
+    }
+
+    fn set_allow_plaintext(&mut self, allow: bool) {
+        self.allow_plaintext = allow;
+    }
+
+    fn delete_snapshots(&mut self, snapshots: &[&SnapshotInfo]) -> Result<()> {
+        for s in snapshots.iter() {
+            fs::remove_file(self.get_snapshot_path(&s.id))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod dir_backend_tests {
+    use anyhow::Result;
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::*;
+
+    #[test]
+    fn test_dir_backend() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut backend = DirBackend::new(dir.path(), None);
+        assert!(backend.fetch_latest()?.is_none());
+
+        let now = Utc.timestamp(Utc::now().timestamp(), 0);
+        for (i, bytes) in [b"older", b"newer"].iter().enumerate() {
+            backend.upload(&SyncData {
+                data_time: now + Duration::seconds(i as i64),
+                db_bytes: bytes.to_vec(),
+                delta: None,
+            })?;
+        }
+
+        assert_eq!(backend.list_snapshots()?.len(), 2);
+        let latest = backend.fetch_latest()?.unwrap();
+        assert_eq!(latest.data_time, now + Duration::seconds(1));
+        assert_eq!(latest.db_bytes, b"newer".to_vec());
+
+        // snapshots pushed before encryption was enabled are read only when allowed
+        let mut encrypted_backend = DirBackend::new(dir.path(), Some("passphrase".to_string()));
+        assert!(encrypted_backend.fetch_latest().is_err());
+        encrypted_backend.set_allow_plaintext(true);
+        assert_eq!(
+            encrypted_backend.fetch_latest()?.unwrap().db_bytes,
+            b"newer".to_vec()
+        );
+
+        assert_eq!(backend.prune(1)?, 1);
+        assert_eq!(backend.list_snapshots()?.len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,435 @@
+use anyhow::{Error, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use lettre::{
+    self,
+    message::{header::ContentType, Attachment},
+    Transport,
+};
+use regex::Regex;
+
+use crate::infra::{
+    build_smtp_transport, connect_imap, get_mail_auth, DeltaInfo, EmailConfig, ImapSession,
+    ImapSyncCursor, MailAuth, PayloadFormat, Secrets, SnapshotInfo, SyncBackend, SyncConfig,
+    SyncData, SyncError,
+};
+
+const SYNC_SUBJECT_PREFIX: &str = "[wordmem][sync]";
+
+/// Sync backend keeping snapshots as attachments of mails in the user's own mailbox.
+pub struct EmailBackend<'a> {
+    email_config: &'a EmailConfig,
+    auth: MailAuth,
+    /// passphrase to encrypt payloads with, if any
+    passphrase: Option<String>,
+    /// whether payloads not encrypted are read though a passphrase is given
+    allow_plaintext: bool,
+    format: PayloadFormat,
+    /// UIDVALIDITY of the sync mailbox in the current connection
+    uid_validity: Option<u32>,
+    /// where the newest sync mail has been seen
+    cursor: Option<ImapSyncCursor>,
+}
+
+impl<'a> EmailBackend<'a> {
+    pub fn new(
+        email_config: &'a EmailConfig,
+        passphrase: Option<String>,
+        secrets: &Secrets,
+    ) -> Result<EmailBackend<'a>> {
+        let auth = get_mail_auth(email_config, secrets)?;
+        if auth.is_none() {
+            return Err(Error::msg("Sync password missing. Please sign in again."));
+        }
+
+        Ok(EmailBackend::with_auth(
+            email_config,
+            auth.unwrap(),
+            passphrase,
+        ))
+    }
+
+    /// Create the backend with the login given, rather than the one kept in system keyring.
+    pub fn with_auth(
+        email_config: &'a EmailConfig,
+        auth: MailAuth,
+        passphrase: Option<String>,
+    ) -> EmailBackend<'a> {
+        EmailBackend {
+            email_config,
+            auth,
+            passphrase,
+            allow_plaintext: false,
+            format: PayloadFormat::default(),
+            uid_validity: None,
+            cursor: email_config.sync_cursor.clone(),
+        }
+    }
+
+    fn connect(&mut self) -> Result<ImapSession> {
+        let mut imap_session = connect_imap(self.email_config, &self.auth)?;
+        let mailbox = imap_session.select(self.email_config.get_sync_mailbox())?;
+        self.uid_validity = mailbox.uid_validity;
+        Ok(imap_session)
+    }
+
+    /// List sync mails in the selected mailbox, newest first. Snapshot ids are UIDs.
+    /// If `from_uid` is given, only mails from that UID on are looked at.
+    fn list_in_session(
+        &self,
+        imap_session: &mut ImapSession,
+        from_uid: Option<u32>,
+    ) -> Result<Vec<SnapshotInfo>> {
+        let mut query = if !self.email_config.workaround_imap_search.unwrap_or(false) {
+            format!("SUBJECT {}", SYNC_SUBJECT_PREFIX)
+        } else {
+            "ALL".to_string()
+        };
+        if let Some(from_uid) = from_uid {
+            query = format!("UID {}:* {}", from_uid, query);
+        }
+        let uid_list = imap_session.uid_search(query)?;
+
+        let mut uid_list: Vec<_> = uid_list.into_iter().collect();
+        uid_list.sort_unstable();
+        uid_list.reverse();
+
+        let regex = Regex::new(r"\[(?P<info>[^\]]*)\]")?;
+        let mut snapshots = Vec::new();
+        for chunk in uid_list.chunks(50) {
+            let uid_set = chunk
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            let fetches = imap_session.uid_fetch(uid_set, "(UID ENVELOPE RFC822.SIZE)")?;
+            for f in fetches.iter() {
+                let subject = f
+                    .envelope()
+                    .and_then(|e| e.subject.as_ref())
+                    .map(|s| String::from_utf8_lossy(s).to_string())
+                    .unwrap_or_default();
+                if !subject.starts_with(SYNC_SUBJECT_PREFIX) || f.uid.is_none() {
+                    continue;
+                }
+
+                let id = f.uid.unwrap().to_string();
+                let infos: Vec<_> = regex
+                    .captures_iter(&subject)
+                    .map(|caps| caps.name("info").unwrap().as_str().to_string())
+                    .collect();
+                let data_time = match infos
+                    .last()
+                    .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                {
+                    Some(data_time) => data_time.with_timezone(&Utc),
+                    None => {
+                        println!(
+                            "Skipped. {}",
+                            SyncError::malformed_mail(&id, "no data time in subject")
+                        );
+                        continue;
+                    }
+                };
+                let delta = infos.iter().find_map(|info| {
+                    let mut parts = info.strip_prefix("delta ")?.split(' ');
+                    Some(DeltaInfo {
+                        device_id: parts.next()?.to_string(),
+                        seq: parts.next()?.parse().ok()?,
+                    })
+                });
+                snapshots.push(SnapshotInfo {
+                    id,
+                    data_time,
+                    size: f.size.map(|s| s as u64),
+                    delta,
+                });
+            }
+        }
+        snapshots.sort_by(|a, b| b.data_time.cmp(&a.data_time));
+
+        Ok(snapshots)
+    }
+}
+
+impl<'a> SyncBackend for EmailBackend<'a> {
+    fn list_snapshots(&mut self) -> Result<Vec<SnapshotInfo>> {
+        let mut imap_session = self.connect()?;
+        let snapshots = self.list_in_session(&mut imap_session, None)?;
+        imap_session.logout()?;
+
+        Ok(snapshots)
+    }
+
+    /// List sync mails after the cursor if it is still valid, and move the cursor forward.
+    fn list_unseen_snapshots(&mut self) -> Result<Vec<SnapshotInfo>> {
+        let mut imap_session = self.connect()?;
+        let from_uid = self
+            .cursor
+            .as_ref()
+            .filter(|c| Some(c.uid_validity) == self.uid_validity)
+            .map(|c| c.last_uid + 1);
+        let mut snapshots = self.list_in_session(&mut imap_session, from_uid)?;
+        imap_session.logout()?;
+
+        // `UID n:*` gives the last mail even if its UID is less than n
+        snapshots.retain(|s| s.id.parse::<u32>().ok() >= from_uid);
+        let last_uid = snapshots.iter().filter_map(|s| s.id.parse().ok()).max();
+        if let (Some(last_uid), Some(uid_validity)) = (last_uid, self.uid_validity) {
+            self.cursor = Some(ImapSyncCursor {
+                uid_validity,
+                last_uid,
+            });
+        }
+
+        Ok(snapshots)
+    }
+
+    /// Move the cursor back to just before the mail.
+    fn keep_unseen_snapshot(&mut self, snapshot: &SnapshotInfo) {
+        if let (Some(cursor), Ok(uid)) = (self.cursor.as_mut(), snapshot.id.parse::<u32>()) {
+            cursor.last_uid = cursor.last_uid.min(uid.saturating_sub(1));
+        }
+    }
+
+    fn fetch(&mut self, snapshot: &SnapshotInfo) -> Result<SyncData> {
+        let mut imap_session = self.connect()?;
+        let fetches = imap_session.uid_fetch(&snapshot.id, "BODY.PEEK[]")?;
+        let malformed = |reason| SyncError::malformed_mail(&snapshot.id, reason);
+        let body = fetches
+            .iter()
+            .next()
+            .and_then(|f| f.body())
+            .ok_or_else(|| malformed("body missing"))?;
+        let message =
+            mail_parser::Message::parse(body).ok_or_else(|| malformed("cannot be parsed"))?;
+        let payload = match message.get_attachment(0) {
+            Some(mail_parser::MessagePart::Binary(part))
+            | Some(mail_parser::MessagePart::InlineBinary(part)) => part.get_contents(),
+            _ => return Err(malformed("attachment missing").into()),
+        };
+        let mut sync_data = SyncData::from_payload(
+            snapshot.data_time,
+            payload,
+            self.passphrase.as_deref(),
+            self.allow_plaintext,
+        )?;
+        sync_data.delta = snapshot.delta.clone();
+        imap_session.logout()?;
+
+        Ok(sync_data)
+    }
+
+    fn upload(&mut self, data: &SyncData) -> Result<()> {
+        let message = lettre::Message::builder()
+            .from(self.email_config.email.parse()?)
+            .to(self.email_config.email.parse()?)
+            .subject(format!(
+                "{}{}[{}]",
+                SYNC_SUBJECT_PREFIX,
+                match &data.delta {
+                    Some(delta) => format!("[delta {} {}]", delta.device_id, delta.seq),
+                    None => String::new(),
+                },
+                data.data_time.to_rfc3339_opts(SecondsFormat::Secs, true)
+            ))
+            .singlepart(
+                Attachment::new(SyncData::get_payload_name(self.format)).body(
+                    data.to_payload(self.format, self.passphrase.as_deref())?,
+                    ContentType::parse("application/octet-stream").unwrap(),
+                ),
+            )?;
+
+        // mails are put into a dedicated mailbox directly, where no mail filter can move them
+        let mailbox = self.email_config.get_sync_mailbox();
+        if mailbox.eq_ignore_ascii_case("INBOX") {
+            build_smtp_transport(self.email_config, &self.auth)?.send(&message)?;
+        } else {
+            let mut imap_session = connect_imap(self.email_config, &self.auth)?;
+            imap_session
+                .append(mailbox, &message.formatted())
+                .finish()?;
+            imap_session.logout()?;
+        }
+
+        Ok(())
+    }
+
+    fn set_payload_format(&mut self, format: PayloadFormat) {
+        self.format = format;
+    }
+
+    fn set_allow_plaintext(&mut self, allow: bool) {
+        self.allow_plaintext = allow;
+    }
+
+    fn delete_snapshots(&mut self, snapshots: &[&SnapshotInfo]) -> Result<()> {
+        let uid_set = snapshots
+            .iter()
+            .map(|s| s.id.clone())
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut imap_session = self.connect()?;
+        if let Some(trash_mailbox) = &self.email_config.trash_mailbox {
+            imap_session.uid_copy(&uid_set, trash_mailbox)?;
+        }
+        delete_by_uids(&mut imap_session, &uid_set)?;
+        imap_session.logout()?;
+
+        Ok(())
+    }
+
+    fn get_updated_config(&self) -> Option<SyncConfig> {
+        if self.cursor == self.email_config.sync_cursor {
+            return None;
+        }
+
+        Some(SyncConfig::Email(EmailConfig {
+            sync_cursor: self.cursor.clone(),
+            ..self.email_config.clone()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod email_backend_tests {
+    use anyhow::Result;
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+    use crate::infra::{ensure_mailbox, FakeMailServer};
+
+    fn build_sync_mail(subject: &str, attachment: Option<&[u8]>) -> Vec<u8> {
+        let builder = lettre::Message::builder()
+            .from("wordmem@localhost".parse().unwrap())
+            .to("wordmem@localhost".parse().unwrap())
+            .subject(subject);
+        match attachment {
+            Some(bytes) => builder
+                .singlepart(
+                    Attachment::new(SyncData::get_payload_name(PayloadFormat::default())).body(
+                        bytes.to_vec(),
+                        ContentType::parse("application/octet-stream").unwrap(),
+                    ),
+                )
+                .unwrap(),
+            None => builder.body("no attachment".to_string()).unwrap(),
+        }
+        .formatted()
+    }
+
+    #[test]
+    fn test_email_backend() -> Result<()> {
+        // a dedicated mailbox searched by subject, and INBOX searched by ALL
+        for (search_subject, sync_mailbox) in [(true, Some("wordmem")), (false, None)] {
+            let server = FakeMailServer::start(search_subject)?;
+            let mut email_config = server.get_email_config(sync_mailbox);
+            email_config.workaround_imap_search = Some(!search_subject);
+            let mailbox = email_config.get_sync_mailbox().to_string();
+            ensure_mailbox(
+                &mut connect_imap(&email_config, &server.get_auth())?,
+                &mailbox,
+            )?;
+            server.add_message(&mailbox, &build_sync_mail("hello", None));
+
+            let mut backend = EmailBackend::with_auth(&email_config, server.get_auth(), None);
+            assert!(backend.list_snapshots()?.is_empty());
+
+            let now = Utc.timestamp(Utc::now().timestamp(), 0);
+            backend.upload(&SyncData {
+                data_time: now,
+                db_bytes: b"full".to_vec(),
+                delta: None,
+            })?;
+            let delta = DeltaInfo {
+                device_id: "abc".to_string(),
+                seq: 7,
+            };
+            backend.upload(&SyncData {
+                data_time: now + Duration::seconds(1),
+                db_bytes: b"delta".to_vec(),
+                delta: Some(delta.clone()),
+            })?;
+
+            let snapshots = backend.list_snapshots()?;
+            assert_eq!(snapshots.len(), 2);
+            assert_eq!(snapshots[0].delta, Some(delta));
+            let latest = backend.fetch_latest()?.unwrap();
+            assert_eq!(latest.data_time, now + Duration::seconds(1));
+            assert_eq!(latest.db_bytes, b"delta".to_vec());
+
+            // the cursor moves past the mails listed
+            assert_eq!(backend.list_unseen_snapshots()?.len(), 2);
+            assert!(backend.list_unseen_snapshots()?.is_empty());
+            assert!(backend.get_updated_config().is_some());
+
+            // sync mails without a valid attachment are listed, but fail to be fetched
+            let time = (now + Duration::seconds(2)).to_rfc3339_opts(SecondsFormat::Secs, true);
+            server.add_message(
+                &mailbox,
+                &build_sync_mail(&format!("{}[{}]", SYNC_SUBJECT_PREFIX, time), None),
+            );
+            let time = (now + Duration::seconds(3)).to_rfc3339_opts(SecondsFormat::Secs, true);
+            server.add_message(
+                &mailbox,
+                &build_sync_mail(
+                    &format!("{}[{}]", SYNC_SUBJECT_PREFIX, time),
+                    Some(b"garbage"),
+                ),
+            );
+            // and those without a data time are skipped
+            server.add_message(
+                &mailbox,
+                &build_sync_mail(&format!("{}[yesterday]", SYNC_SUBJECT_PREFIX), None),
+            );
+            let snapshots = backend.list_unseen_snapshots()?;
+            assert_eq!(snapshots.len(), 2);
+            for s in snapshots.iter() {
+                let e = backend.fetch(s).unwrap_err();
+                assert!(e.downcast_ref::<SyncError>().is_some());
+                backend.keep_unseen_snapshot(s);
+            }
+            // and listed again if kept unseen
+            assert_eq!(backend.list_unseen_snapshots()?.len(), 2);
+
+            backend.delete_snapshots(&snapshots.iter().collect::<Vec<_>>())?;
+            assert_eq!(backend.list_snapshots()?.len(), 2);
+            assert_eq!(server.count_messages(&mailbox), Some(4));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_to_trash() -> Result<()> {
+        let server = FakeMailServer::start(true)?;
+        let mut email_config = server.get_email_config(Some("wordmem"));
+        email_config.trash_mailbox = Some("Trash".to_string());
+        let mut imap_session = connect_imap(&email_config, &server.get_auth())?;
+        ensure_mailbox(&mut imap_session, "wordmem")?;
+        ensure_mailbox(&mut imap_session, "Trash")?;
+
+        let mut backend = EmailBackend::with_auth(&email_config, server.get_auth(), None);
+        let now = Utc.timestamp(Utc::now().timestamp(), 0);
+        for i in 0..3 {
+            backend.upload(&SyncData {
+                data_time: now + Duration::seconds(i),
+                db_bytes: b"full".to_vec(),
+                delta: None,
+            })?;
+        }
+
+        // pruned mails are copied to trash, then expunged from the sync mailbox
+        assert_eq!(backend.prune(1)?, 2);
+        let snapshots = backend.list_snapshots()?;
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].data_time, now + Duration::seconds(2));
+        assert_eq!(server.count_messages("wordmem"), Some(1));
+        assert_eq!(server.count_messages("Trash"), Some(2));
+
+        // the newest full snapshot is kept whatever is asked
+        assert_eq!(backend.prune(0)?, 0);
+        assert_eq!(server.count_messages("wordmem"), Some(1));
+
+        Ok(())
+    }
+}
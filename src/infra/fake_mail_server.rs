@@ -0,0 +1,619 @@
+//! In-process stand-ins for IMAP and SMTP servers, speaking just enough of the protocols for the
+//! mail clients used by wordmem, and the listener other stub servers of tests are built on. Used
+//! by tests only.
+
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use anyhow::Result;
+
+use crate::infra::{parse_imap_id_params, EmailConfig, Encryption, MailAuth, MailLogin};
+
+const FAKE_EMAIL: &str = "wordmem@localhost";
+const FAKE_PASSWORD: &str = "password";
+
+struct FakeMessage {
+    uid: u32,
+    bytes: Vec<u8>,
+    deleted: bool,
+}
+
+struct FakeMailbox {
+    uid_validity: u32,
+    next_uid: u32,
+    messages: Vec<FakeMessage>,
+}
+
+impl FakeMailbox {
+    fn new(uid_validity: u32) -> FakeMailbox {
+        FakeMailbox {
+            uid_validity,
+            next_uid: 1,
+            messages: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, bytes: Vec<u8>) {
+        self.messages.push(FakeMessage {
+            uid: self.next_uid,
+            bytes,
+            deleted: false,
+        });
+        self.next_uid += 1;
+    }
+}
+
+struct FakeMailStore {
+    mailboxes: BTreeMap<String, FakeMailbox>,
+    /// whether SEARCH SUBJECT finds anything, which some servers fail to do
+    search_subject: bool,
+    /// whether SELECT is refused until the client sends IMAP ID, as 163.com does
+    require_id: bool,
+    /// fields the client identified itself by at last
+    client_id: Option<BTreeMap<String, String>>,
+}
+
+type SharedStore = Arc<Mutex<FakeMailStore>>;
+
+/// IMAP and SMTP servers on localhost sharing one account, whose mails live in memory.
+pub struct FakeMailServer {
+    imap_port: u16,
+    smtp_port: u16,
+    store: SharedStore,
+}
+
+impl FakeMailServer {
+    /// Start the servers in background threads. With `search_subject` false, searching by
+    /// subject finds nothing.
+    pub fn start(search_subject: bool) -> Result<FakeMailServer> {
+        let mut mailboxes = BTreeMap::new();
+        mailboxes.insert("INBOX".to_string(), FakeMailbox::new(1));
+        let store = Arc::new(Mutex::new(FakeMailStore {
+            mailboxes,
+            search_subject,
+            require_id: false,
+            client_id: None,
+        }));
+
+        let imap_store = store.clone();
+        let imap_port = serve_local(move |stream| serve_imap(stream, imap_store.clone()))?;
+        let smtp_store = store.clone();
+        let smtp_port = serve_local(move |stream| serve_smtp(stream, smtp_store.clone()))?;
+        Ok(FakeMailServer {
+            imap_port,
+            smtp_port,
+            store,
+        })
+    }
+
+    pub fn get_email_config(&self, sync_mailbox: Option<&str>) -> EmailConfig {
+        EmailConfig {
+            imap_server_host: "127.0.0.1".to_string(),
+            imap_server_port: self.imap_port,
+            imap_encryption: Encryption::None,
+            smtp_server_host: "127.0.0.1".to_string(),
+            smtp_server_port: self.smtp_port,
+            smtp_encryption: Encryption::None,
+            email: FAKE_EMAIL.to_string(),
+            workaround_imap_search: None,
+            trash_mailbox: None,
+            sync_mailbox: sync_mailbox.map(|m| m.to_string()),
+            sync_cursor: None,
+            imap_id: None,
+            tls: None,
+            // not through any proxy in environment
+            proxy: Some(String::new()),
+            oauth2: None,
+        }
+    }
+
+    pub fn get_auth(&self) -> MailAuth {
+        MailLogin::Password(FAKE_PASSWORD.to_string()).into()
+    }
+
+    /// Refuse SELECT until the client sends IMAP ID.
+    pub fn require_id(&self) {
+        self.store.lock().unwrap().require_id = true;
+    }
+
+    /// Get the fields the client identified itself by at last, or `None` if none did.
+    pub fn get_client_id(&self) -> Option<BTreeMap<String, String>> {
+        self.store.lock().unwrap().client_id.clone()
+    }
+
+    /// Put a raw message into the mailbox, creating the mailbox if missing.
+    pub fn add_message(&self, mailbox: &str, bytes: &[u8]) {
+        let mut store = self.store.lock().unwrap();
+        let uid_validity = store.mailboxes.len() as u32 + 1;
+        store
+            .mailboxes
+            .entry(normalize_mailbox(mailbox))
+            .or_insert_with(|| FakeMailbox::new(uid_validity))
+            .add(bytes.to_vec());
+    }
+
+    /// Count messages in the mailbox, or `None` if it does not exist.
+    pub fn count_messages(&self, mailbox: &str) -> Option<usize> {
+        let store = self.store.lock().unwrap();
+        store
+            .mailboxes
+            .get(&normalize_mailbox(mailbox))
+            .map(|m| m.messages.len())
+    }
+}
+
+fn normalize_mailbox(mailbox: &str) -> String {
+    if mailbox.eq_ignore_ascii_case("INBOX") {
+        "INBOX".to_string()
+    } else {
+        mailbox.to_string()
+    }
+}
+
+/// Listen on a free local port, serving each connection by the handler in its own thread.
+/// Returns the port.
+pub fn serve_local<F>(handler: F) -> Result<u16>
+where
+    F: Fn(TcpStream) -> Result<()> + Clone + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let handler = handler.clone();
+            thread::spawn(move || handler(stream));
+        }
+    });
+
+    Ok(port)
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        String::from_utf8_lossy(&line)
+            .trim_end_matches(&['\r', '\n'][..])
+            .to_string(),
+    ))
+}
+
+/// Split IMAP command arguments into atoms, quoted strings (unquoted) and parenthesized lists.
+fn split_args(s: &str) -> Vec<String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut args = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            ' ' => i += 1,
+            '"' => {
+                let mut arg = String::new();
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' && i + 1 < chars.len() {
+                        i += 1;
+                    }
+                    arg.push(chars[i]);
+                    i += 1;
+                }
+                args.push(arg);
+                i += 1;
+            }
+            '(' => {
+                let start = i;
+                let mut depth = 0;
+                while i < chars.len() {
+                    match chars[i] {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    i += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                args.push(chars[start..i].iter().collect());
+            }
+            _ => {
+                let start = i;
+                while i < chars.len() && chars[i] != ' ' {
+                    i += 1;
+                }
+                args.push(chars[start..i].iter().collect());
+            }
+        }
+    }
+
+    args
+}
+
+/// Tell whether the number is in an IMAP sequence set like `1,3:5,7:*`.
+fn in_set(set: &str, n: u32, max: u32) -> bool {
+    let parse = |s: &str| {
+        if s == "*" {
+            max
+        } else {
+            s.parse().unwrap_or(0)
+        }
+    };
+    set.split(',').any(|part| match part.split_once(':') {
+        Some((a, b)) => {
+            let (a, b) = (parse(a), parse(b));
+            a.min(b) <= n && n <= a.max(b)
+        }
+        None => parse(part) == n,
+    })
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn get_subject(bytes: &[u8]) -> String {
+    mail_parser::Message::parse(bytes)
+        .and_then(|m| m.get_subject().map(|s| s.to_string()))
+        .unwrap_or_default()
+}
+
+fn serve_imap(stream: TcpStream, store: SharedStore) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    writer.write_all(b"* OK fake IMAP server ready\r\n")?;
+
+    let mut selected: Option<String> = None;
+    let mut identified = false;
+    while let Some(line) = read_line(&mut reader)? {
+        let (tag, rest) = line.split_once(' ').unwrap_or((&line, ""));
+        let mut args = split_args(rest);
+        if args.is_empty() {
+            writer.write_all(format!("{} BAD empty command\r\n", tag).as_bytes())?;
+            continue;
+        }
+        let mut command = args.remove(0).to_uppercase();
+        let by_uid = command == "UID" && !args.is_empty();
+        if by_uid {
+            command = args.remove(0).to_uppercase();
+        }
+
+        let mut out = Vec::new();
+        let status = match command.as_str() {
+            "CAPABILITY" => {
+                out.extend_from_slice(b"* CAPABILITY IMAP4rev1 AUTH=XOAUTH2 ID UIDPLUS\r\n");
+                "OK CAPABILITY completed".to_string()
+            }
+            "ID" => {
+                let params = rest.split_once(' ').map(|(_, p)| p).unwrap_or_default();
+                store.lock().unwrap().client_id = Some(parse_imap_id_params(params));
+                identified = true;
+                out.extend_from_slice(b"* ID (\"name\" \"fake\" \"vendor\" \"wordmem\")\r\n");
+                "OK ID completed".to_string()
+            }
+            "SELECT" | "EXAMINE" if store.lock().unwrap().require_id && !identified => {
+                "NO SELECT Unsafe Login. Please contact kefu@188.com for help".to_string()
+            }
+            "LOGIN" => {
+                if args.len() == 2 && args[0] == FAKE_EMAIL && args[1] == FAKE_PASSWORD {
+                    "OK LOGIN completed".to_string()
+                } else {
+                    "NO [AUTHENTICATIONFAILED] invalid credentials".to_string()
+                }
+            }
+            "AUTHENTICATE" => {
+                writer.write_all(b"+ \r\n")?;
+                let response = read_line(&mut reader)?.unwrap_or_default();
+                let decoded = base64::decode(response.trim()).unwrap_or_default();
+                let expected = format!("user={}\x01auth=Bearer {}", FAKE_EMAIL, FAKE_PASSWORD);
+                if String::from_utf8_lossy(&decoded).starts_with(&expected) {
+                    "OK AUTHENTICATE completed".to_string()
+                } else {
+                    "NO [AUTHENTICATIONFAILED] invalid token".to_string()
+                }
+            }
+            "NOOP" => "OK NOOP completed".to_string(),
+            "LOGOUT" => {
+                writer.write_all(format!("* BYE\r\n{} OK LOGOUT completed\r\n", tag).as_bytes())?;
+                return Ok(());
+            }
+            "LIST" => {
+                let pattern = args.get(1).cloned().unwrap_or_default();
+                let store = store.lock().unwrap();
+                for name in store.mailboxes.keys() {
+                    if pattern == "*" || normalize_mailbox(&pattern) == *name {
+                        out.extend_from_slice(
+                            format!("* LIST () \"/\" {}\r\n", quote(name)).as_bytes(),
+                        );
+                    }
+                }
+                "OK LIST completed".to_string()
+            }
+            "CREATE" => {
+                let name = normalize_mailbox(&args.get(0).cloned().unwrap_or_default());
+                let mut store = store.lock().unwrap();
+                if store.mailboxes.contains_key(&name) {
+                    "NO [ALREADYEXISTS] mailbox exists".to_string()
+                } else {
+                    let uid_validity = store.mailboxes.len() as u32 + 1;
+                    store.mailboxes.insert(name, FakeMailbox::new(uid_validity));
+                    "OK CREATE completed".to_string()
+                }
+            }
+            "SELECT" | "EXAMINE" => {
+                let name = normalize_mailbox(&args.get(0).cloned().unwrap_or_default());
+                let store = store.lock().unwrap();
+                match store.mailboxes.get(&name) {
+                    Some(mailbox) => {
+                        out.extend_from_slice(
+                            format!(
+                                "* FLAGS (\\Seen \\Deleted)\r\n* {} EXISTS\r\n* 0 RECENT\r\n* OK [UIDVALIDITY {}] UIDs valid\r\n* OK [UIDNEXT {}] predicted next UID\r\n",
+                                mailbox.messages.len(),
+                                mailbox.uid_validity,
+                                mailbox.next_uid
+                            )
+                            .as_bytes(),
+                        );
+                        selected = Some(name);
+                        "OK [READ-WRITE] SELECT completed".to_string()
+                    }
+                    None => "NO mailbox does not exist".to_string(),
+                }
+            }
+            "APPEND" => {
+                let name = normalize_mailbox(&args.get(0).cloned().unwrap_or_default());
+                let len = args
+                    .last()
+                    .and_then(|a| a.strip_prefix('{'))
+                    .and_then(|a| a.trim_end_matches('}').trim_end_matches('+').parse().ok());
+                match len {
+                    Some(len) => {
+                        writer.write_all(b"+ ready for literal data\r\n")?;
+                        let mut bytes = vec![0; len];
+                        reader.read_exact(&mut bytes)?;
+                        read_line(&mut reader)?;
+                        let mut store = store.lock().unwrap();
+                        match store.mailboxes.get_mut(&name) {
+                            Some(mailbox) => {
+                                mailbox.add(bytes);
+                                "OK APPEND completed".to_string()
+                            }
+                            None => "NO [TRYCREATE] mailbox does not exist".to_string(),
+                        }
+                    }
+                    None => "BAD literal expected".to_string(),
+                }
+            }
+            _ if selected.is_none() => "BAD no mailbox selected".to_string(),
+            "SEARCH" => {
+                let store = store.lock().unwrap();
+                let search_subject = store.search_subject;
+                let mailbox = store.mailboxes.get(selected.as_ref().unwrap()).unwrap();
+                let max_uid = mailbox.next_uid - 1;
+                let mut found = Vec::new();
+                for (i, m) in mailbox.messages.iter().enumerate() {
+                    let mut matched = true;
+                    let mut criteria = args.iter();
+                    while let Some(key) = criteria.next() {
+                        match key.to_uppercase().as_str() {
+                            "UID" => {
+                                let set = criteria.next().cloned().unwrap_or_default();
+                                matched &= in_set(&set, m.uid, max_uid);
+                            }
+                            "SUBJECT" => {
+                                let text = criteria.next().cloned().unwrap_or_default();
+                                matched &= search_subject
+                                    && get_subject(&m.bytes)
+                                        .to_lowercase()
+                                        .contains(&text.to_lowercase());
+                            }
+                            _ => {}
+                        }
+                    }
+                    if matched {
+                        found.push(if by_uid { m.uid } else { i as u32 + 1 });
+                    }
+                }
+                let mut response = "* SEARCH".to_string();
+                for n in found {
+                    response += &format!(" {}", n);
+                }
+                out.extend_from_slice(format!("{}\r\n", response).as_bytes());
+                "OK SEARCH completed".to_string()
+            }
+            "FETCH" => {
+                let set = args.get(0).cloned().unwrap_or_default();
+                let items = args.get(1..).unwrap_or(&[]).join(" ").to_uppercase();
+                let store = store.lock().unwrap();
+                let mailbox = store.mailboxes.get(selected.as_ref().unwrap()).unwrap();
+                let max = if by_uid {
+                    mailbox.next_uid - 1
+                } else {
+                    mailbox.messages.len() as u32
+                };
+                for (i, m) in mailbox.messages.iter().enumerate() {
+                    let seq = i as u32 + 1;
+                    if !in_set(&set, if by_uid { m.uid } else { seq }, max) {
+                        continue;
+                    }
+
+                    let mut fields = Vec::new();
+                    if by_uid || items.contains("UID") {
+                        fields.push(format!("UID {}", m.uid).into_bytes());
+                    }
+                    if items.contains("RFC822.SIZE") {
+                        fields.push(format!("RFC822.SIZE {}", m.bytes.len()).into_bytes());
+                    }
+                    if items.contains("ENVELOPE") {
+                        fields.push(
+                            format!(
+                                "ENVELOPE (NIL {} NIL NIL NIL NIL NIL NIL NIL NIL)",
+                                quote(&get_subject(&m.bytes))
+                            )
+                            .into_bytes(),
+                        );
+                    }
+                    if items.contains("HEADER.FIELDS (SUBJECT)") {
+                        let header = format!("Subject: {}\r\n\r\n", get_subject(&m.bytes));
+                        let mut field =
+                            format!("BODY[HEADER.FIELDS (SUBJECT)] {{{}}}\r\n", header.len())
+                                .into_bytes();
+                        field.extend_from_slice(header.as_bytes());
+                        fields.push(field);
+                    }
+                    if items.contains("BODY[]") || items.contains("BODY.PEEK[]") {
+                        let mut field = format!("BODY[] {{{}}}\r\n", m.bytes.len()).into_bytes();
+                        field.extend_from_slice(&m.bytes);
+                        fields.push(field);
+                    }
+
+                    out.extend_from_slice(format!("* {} FETCH (", seq).as_bytes());
+                    out.extend_from_slice(&fields.join(&b' '));
+                    out.extend_from_slice(b")\r\n");
+                }
+                "OK FETCH completed".to_string()
+            }
+            "STORE" => {
+                let set = args.get(0).cloned().unwrap_or_default();
+                let deleted = args
+                    .get(1..)
+                    .unwrap_or(&[])
+                    .join(" ")
+                    .to_uppercase()
+                    .contains("\\DELETED");
+                let mut store = store.lock().unwrap();
+                let mailbox = store.mailboxes.get_mut(selected.as_ref().unwrap()).unwrap();
+                let max_uid = mailbox.next_uid - 1;
+                let max_seq = mailbox.messages.len() as u32;
+                for (i, m) in mailbox.messages.iter_mut().enumerate() {
+                    let (n, max) = if by_uid {
+                        (m.uid, max_uid)
+                    } else {
+                        (i as u32 + 1, max_seq)
+                    };
+                    if deleted && in_set(&set, n, max) {
+                        m.deleted = true;
+                    }
+                }
+                "OK STORE completed".to_string()
+            }
+            "COPY" => {
+                let set = args.get(0).cloned().unwrap_or_default();
+                let target = normalize_mailbox(&args.get(1).cloned().unwrap_or_default());
+                let mut store = store.lock().unwrap();
+                let mailbox = store.mailboxes.get(selected.as_ref().unwrap()).unwrap();
+                let max = if by_uid {
+                    mailbox.next_uid - 1
+                } else {
+                    mailbox.messages.len() as u32
+                };
+                let copied: Vec<_> = mailbox
+                    .messages
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, m)| in_set(&set, if by_uid { m.uid } else { *i as u32 + 1 }, max))
+                    .map(|(_, m)| m.bytes.clone())
+                    .collect();
+                match store.mailboxes.get_mut(&target) {
+                    Some(target) => {
+                        for bytes in copied {
+                            target.add(bytes);
+                        }
+                        "OK COPY completed".to_string()
+                    }
+                    None => "NO [TRYCREATE] mailbox does not exist".to_string(),
+                }
+            }
+            "EXPUNGE" => {
+                // UID EXPUNGE of UIDPLUS only expunges mails in the UID set
+                let set = args.get(0).cloned();
+                let mut store = store.lock().unwrap();
+                let mailbox = store.mailboxes.get_mut(selected.as_ref().unwrap()).unwrap();
+                let max_uid = mailbox.next_uid - 1;
+                let mut i = 0;
+                while i < mailbox.messages.len() {
+                    let m = &mailbox.messages[i];
+                    let in_uid_set = match (by_uid, &set) {
+                        (true, Some(set)) => in_set(set, m.uid, max_uid),
+                        _ => true,
+                    };
+                    if m.deleted && in_uid_set {
+                        mailbox.messages.remove(i);
+                        out.extend_from_slice(format!("* {} EXPUNGE\r\n", i + 1).as_bytes());
+                    } else {
+                        i += 1;
+                    }
+                }
+                "OK EXPUNGE completed".to_string()
+            }
+            _ => "BAD unknown command".to_string(),
+        };
+
+        out.extend_from_slice(format!("{} {}\r\n", tag, status).as_bytes());
+        writer.write_all(&out)?;
+    }
+
+    Ok(())
+}
+
+fn serve_smtp(stream: TcpStream, store: SharedStore) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    writer.write_all(b"220 localhost fake ESMTP\r\n")?;
+
+    while let Some(line) = read_line(&mut reader)? {
+        let (command, arg) = line.split_once(' ').unwrap_or((&line, ""));
+        let reply = match command.to_uppercase().as_str() {
+            "EHLO" | "HELO" => {
+                "250-localhost\r\n250-AUTH PLAIN XOAUTH2\r\n250 8BITMIME".to_string()
+            }
+            "AUTH" => {
+                let (mechanism, response) = arg.split_once(' ').unwrap_or((arg, ""));
+                let decoded = base64::decode(response.trim()).unwrap_or_default();
+                let decoded = String::from_utf8_lossy(&decoded);
+                let authenticated = match mechanism.to_uppercase().as_str() {
+                    "PLAIN" => decoded == format!("\0{}\0{}", FAKE_EMAIL, FAKE_PASSWORD),
+                    "XOAUTH2" => decoded.starts_with(&format!(
+                        "user={}\x01auth=Bearer {}",
+                        FAKE_EMAIL, FAKE_PASSWORD
+                    )),
+                    _ => false,
+                };
+                if authenticated {
+                    "235 authentication succeeded".to_string()
+                } else {
+                    "535 authentication failed".to_string()
+                }
+            }
+            "MAIL" | "RCPT" | "RSET" | "NOOP" => "250 OK".to_string(),
+            "DATA" => {
+                writer.write_all(b"354 end data with <CR><LF>.<CR><LF>\r\n")?;
+                let mut bytes = Vec::new();
+                while let Some(line) = read_line(&mut reader)? {
+                    if line == "." {
+                        break;
+                    }
+                    bytes.extend_from_slice(line.strip_prefix('.').unwrap_or(&line).as_bytes());
+                    bytes.extend_from_slice(b"\r\n");
+                }
+                let mut store = store.lock().unwrap();
+                store.mailboxes.get_mut("INBOX").unwrap().add(bytes);
+                "250 OK queued".to_string()
+            }
+            "QUIT" => {
+                writer.write_all(b"221 bye\r\n")?;
+                return Ok(());
+            }
+            _ => "502 command not implemented".to_string(),
+        };
+        writer.write_all(format!("{}\r\n", reply).as_bytes())?;
+    }
+
+    Ok(())
+}
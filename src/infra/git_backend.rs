@@ -0,0 +1,244 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Error, Result};
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+
+use crate::infra::{text_to_words, words_to_text, Db, SnapshotInfo, SyncBackend, SyncData};
+
+const WORDS_FILE_NAME: &str = "words.tsv";
+
+/// Sync backend keeping words as a text file in a git repository, so that every sync is a commit.
+pub struct GitBackend {
+    /// local clone used as working copy
+    repo_dir: PathBuf,
+    remote: String,
+    branch: String,
+}
+
+impl GitBackend {
+    pub fn new<T>(repo_dir: T, remote: &str, branch: &str) -> GitBackend
+    where
+        T: AsRef<Path>,
+    {
+        GitBackend {
+            repo_dir: repo_dir.as_ref().to_path_buf(),
+            remote: remote.to_string(),
+            branch: branch.to_string(),
+        }
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.repo_dir)
+            .args(args)
+            .output()?;
+        if !output.status.success() {
+            return Err(Error::msg(format!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn remote_ref(&self) -> String {
+        format!("origin/{}", self.branch)
+    }
+
+    /// Clone the remote if needed, and bring the working copy to the newest remote commit.
+    /// Returns whether the remote branch exists.
+    fn sync_working_copy(&self) -> Result<bool> {
+        if !self.repo_dir.join(".git").exists() {
+            fs::create_dir_all(&self.repo_dir)?;
+            self.git(&["init"])?;
+            self.git(&["remote", "add", "origin", &self.remote])?;
+        }
+
+        self.git(&["fetch", "origin"])?;
+        if self
+            .git(&["rev-parse", "--verify", "--quiet", &self.remote_ref()])
+            .is_err()
+        {
+            return Ok(false);
+        }
+
+        // the working copy holds nothing of its own: all data lives in local DB
+        self.git(&["checkout", "-B", &self.branch, &self.remote_ref()])?;
+        self.git(&["reset", "--hard", &self.remote_ref()])?;
+        Ok(true)
+    }
+
+    fn get_words_path(&self) -> PathBuf {
+        self.repo_dir.join(WORDS_FILE_NAME)
+    }
+}
+
+impl SyncBackend for GitBackend {
+    fn list_snapshots(&mut self) -> Result<Vec<SnapshotInfo>> {
+        if !self.sync_working_copy()? {
+            return Ok(Vec::new());
+        }
+
+        let log = self.git(&[
+            "log",
+            "--format=%H %ct",
+            &self.remote_ref(),
+            "--",
+            WORDS_FILE_NAME,
+        ])?;
+        let mut snapshots = Vec::new();
+        for line in log.lines() {
+            let (hash, time) = match line.split_once(' ') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            snapshots.push(SnapshotInfo {
+                id: hash.to_string(),
+                data_time: DateTime::from_utc(
+                    NaiveDateTime::from_timestamp(time.trim().parse()?, 0),
+                    Utc,
+                ),
+                size: None,
+                delta: None,
+            });
+        }
+
+        Ok(snapshots)
+    }
+
+    fn fetch(&mut self, snapshot: &SnapshotInfo) -> Result<SyncData> {
+        // rebuild a DB from the text file, so that it is merged the same way as other backends
+        let text = self.git(&["show", &format!("{}:{}", snapshot.id, WORDS_FILE_NAME)])?;
+        let words = text_to_words(&text)?;
+        let db_file = tempfile::NamedTempFile::new()?;
+        let db = Db::new(db_file.path())?;
+        for w in words.iter() {
+            db.put_synced_word(w)?;
+        }
+
+        Ok(SyncData {
+            data_time: snapshot.data_time,
+            db_bytes: db.read_bytes()?,
+            delta: None,
+        })
+    }
+
+    fn upload(&mut self, data: &SyncData) -> Result<()> {
+        if data.delta.is_some() {
+            return Err(Error::msg(
+                "The git sync backend takes full snapshots only.",
+            ));
+        }
+        self.sync_working_copy()?;
+
+        let db_file = tempfile::NamedTempFile::new()?;
+        fs::write(db_file.path(), &data.db_bytes)?;
+        let words = Db::new(db_file.path())?.get_all_words_for_sync()?;
+        fs::write(self.get_words_path(), words_to_text(&words))?;
+
+        self.git(&["add", WORDS_FILE_NAME])?;
+        if self.git(&["status", "--porcelain"])?.trim().is_empty() {
+            return Ok(());
+        }
+
+        let data_time = data.data_time.to_rfc3339_opts(SecondsFormat::Secs, true);
+        let message = format!("wordmem sync [{}]", data_time);
+        let mut commit = Command::new("git");
+        commit.arg("-C").arg(&self.repo_dir);
+        // commit without asking for an identity when git has none configured
+        if self.git(&["config", "user.email"]).is_err() {
+            commit.args([
+                "-c",
+                "user.name=wordmem",
+                "-c",
+                "user.email=wordmem@localhost",
+            ]);
+        }
+        let output = commit
+            .args(["commit", "-m", &message, "--date", &data_time])
+            .env("GIT_COMMITTER_DATE", &data_time)
+            .output()?;
+        if !output.status.success() {
+            return Err(Error::msg(format!(
+                "git commit failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        self.git(&["push", "origin", &format!("HEAD:{}", self.branch)])?;
+        Ok(())
+    }
+
+    /// The word list is rewritten as a whole in every commit.
+    fn supports_delta(&self) -> bool {
+        false
+    }
+
+    fn delete_snapshots(&mut self, _snapshots: &[&SnapshotInfo]) -> Result<()> {
+        Ok(())
+    }
+
+    /// History is what the git backend is for, so nothing is pruned.
+    fn prune(&mut self, _keep: usize) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod git_backend_tests {
+    use anyhow::Result;
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+    use crate::infra::Word;
+
+    #[test]
+    fn test_git_backend() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let remote = dir.path().join("remote.git");
+        let status = Command::new("git")
+            .args(["init", "--bare"])
+            .arg(&remote)
+            .status()?;
+        assert!(status.success());
+        let remote = remote.to_string_lossy().to_string();
+
+        let mut backend_a = GitBackend::new(dir.path().join("a"), &remote, "main");
+        let mut backend_b = GitBackend::new(dir.path().join("b"), &remote, "main");
+        assert!(backend_b.fetch_latest()?.is_none());
+
+        let db_file = tempfile::NamedTempFile::new()?;
+        let db = Db::new(db_file.path())?;
+        db.insert_word(&Word::from_name_and_meanings("hello", "a greeting"))?;
+        let now = Utc.timestamp(Utc::now().timestamp(), 0);
+        backend_a.upload(&SyncData {
+            data_time: now,
+            db_bytes: db.read_bytes()?,
+            delta: None,
+        })?;
+
+        db.insert_word(&Word::from_name_and_meanings("world", "the earth"))?;
+        backend_a.upload(&SyncData {
+            data_time: now + Duration::seconds(1),
+            db_bytes: db.read_bytes()?,
+            delta: None,
+        })?;
+
+        assert_eq!(backend_b.list_snapshots()?.len(), 2);
+        let latest = backend_b.fetch_latest()?.unwrap();
+        assert_eq!(latest.data_time, now + Duration::seconds(1));
+
+        let latest_file = tempfile::NamedTempFile::new()?;
+        fs::write(latest_file.path(), latest.db_bytes)?;
+        assert_eq!(Db::new(latest_file.path())?.get_all_words()?.len(), 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use std::path::Path;
+
+use anyhow::{Error, Result};
+use sqlite::Connection;
+
+/// Category of words marked as mastered in Kindle Vocabulary Builder.
+const KINDLE_MASTERED_CATEGORY: i64 = 100;
+
+#[derive(Debug, PartialEq)]
+pub struct KindleLookup {
+    pub word: String,
+    pub usage: String,
+    pub book_title: String,
+}
+
+/// Read the lookups of words which are not mastered yet from a Kindle `vocab.db`.
+/// The stem of a word is preferred over the looked-up form.
+pub fn read_kindle_lookups<T>(file: T) -> Result<Vec<KindleLookup>>
+where
+    T: AsRef<Path>,
+{
+    if !file.as_ref().exists() {
+        return Err(Error::msg(format!(
+            "Kindle vocabulary file not found: {}",
+            file.as_ref().display()
+        )));
+    }
+
+    let conn = Connection::open(file)?;
+    let mut lookups = Vec::new();
+    conn.iterate(
+        format!(
+            "SELECT
+                COALESCE(NULLIF(TRIM(w.stem), ''), w.word) AS word,
+                COALESCE(l.usage, '') AS usage,
+                COALESCE(b.title, '') AS book_title
+            FROM LOOKUPS l
+                JOIN WORDS w ON l.word_key = w.id
+                LEFT JOIN BOOK_INFO b ON l.book_key = b.id
+            WHERE COALESCE(w.category, 0) != {}
+            ORDER BY l.timestamp ASC;",
+            KINDLE_MASTERED_CATEGORY
+        ),
+        |pairs| {
+            let get = |i: usize| pairs[i].1.unwrap_or_default().trim().to_string();
+            lookups.push(KindleLookup {
+                word: get(0),
+                usage: get(1),
+                book_title: get(2),
+            });
+            true
+        },
+    )?;
+
+    Ok(lookups.into_iter().filter(|l| !l.word.is_empty()).collect())
+}
+
+#[cfg(test)]
+mod kindle_tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_read_kindle_lookups() -> Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        let conn = Connection::open(file.path())?;
+        conn.execute(
+            "CREATE TABLE WORDS (id TEXT PRIMARY KEY NOT NULL, word TEXT, stem TEXT, lang TEXT,
+                category INTEGER DEFAULT 0, timestamp INTEGER DEFAULT 0, profileid TEXT);
+            CREATE TABLE LOOKUPS (id TEXT PRIMARY KEY NOT NULL, word_key TEXT, book_key TEXT,
+                dict_key TEXT, pos TEXT, usage TEXT, timestamp INTEGER DEFAULT 0);
+            CREATE TABLE BOOK_INFO (id TEXT PRIMARY KEY NOT NULL, asin TEXT, guid TEXT, lang TEXT,
+                title TEXT, authors TEXT);
+            INSERT INTO WORDS VALUES ('en:running', 'running', 'run', 'en', 0, 1, '');
+            INSERT INTO WORDS VALUES ('en:known', 'known', 'know', 'en', 100, 2, '');
+            INSERT INTO BOOK_INFO VALUES ('b1', 'asin', 'guid', 'en', 'A Book', 'Someone');
+            INSERT INTO LOOKUPS VALUES ('l1', 'en:running', 'b1', '', '', 'He was running.', 3);
+            INSERT INTO LOOKUPS VALUES ('l2', 'en:known', 'b1', '', '', 'It is known.', 4);",
+        )?;
+
+        let lookups = read_kindle_lookups(file.path())?;
+        assert_eq!(
+            lookups,
+            vec![KindleLookup {
+                word: "run".to_string(),
+                usage: "He was running.".to_string(),
+                book_title: "A Book".to_string(),
+            }]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::infra::Word;
+
+/// Result of merging local words with remote ones.
+#[derive(Debug, Default)]
+pub struct MergeResult {
+    /// words to be written to local DB as they are
+    pub local_updates: Vec<Word>,
+    /// names of words taken from remote
+    pub pulled: Vec<String>,
+    /// names of words changed locally only, which remote lacks
+    pub pushed: Vec<String>,
+    /// names of words changed on both sides and merged field by field
+    pub merged: Vec<String>,
+}
+
+impl MergeResult {
+    pub fn is_empty(&self) -> bool {
+        self.pulled.is_empty() && self.pushed.is_empty() && self.merged.is_empty()
+    }
+}
+
+/// Merge remote words into local ones by three-way comparison against the time of last sync.
+///
+/// A word changed on one side only is taken from that side. A word changed on both sides since
+/// `base_time` is merged field by field: meanings are taken from the side changed later, so that a
+/// meaning deleted there stays deleted, examples and tags are united, and the visit info is taken
+/// from the side visited later. Without `base_time` every difference counts as a conflict.
+/// Tombstones of deleted words take part like any other change, except that a conflict involving
+/// a deletion is settled by the newer change instead of merging fields.
+pub fn merge_words(
+    local: Vec<Word>,
+    remote: Vec<Word>,
+    base_time: Option<DateTime<Utc>>,
+    device_id: &str,
+    now: DateTime<Utc>,
+) -> MergeResult {
+    let changed = |w: &Word| base_time.map_or(true, |t| w.updated_at > t);
+
+    let mut pairs: BTreeMap<String, (Option<Word>, Option<Word>)> = BTreeMap::new();
+    for w in local {
+        pairs.entry(w.name.clone()).or_default().0 = Some(w);
+    }
+    for w in remote {
+        pairs.entry(w.name.clone()).or_default().1 = Some(w);
+    }
+
+    let mut result = MergeResult::default();
+    for (name, pair) in pairs {
+        match pair {
+            (Some(_), None) => result.pushed.push(name),
+            (None, Some(r)) => {
+                result.local_updates.push(r);
+                result.pulled.push(name);
+            }
+            (Some(l), Some(r)) => {
+                if l.content_eq(&r) {
+                    continue;
+                }
+
+                let deletion = l.deleted_at.is_some() || r.deleted_at.is_some();
+                let take_remote = match (changed(&l), changed(&r)) {
+                    (true, true) if !deletion => {
+                        let mut merged = merge_fields(l, &r);
+                        merged.updated_at = now;
+                        merged.device_id = device_id.to_string();
+                        result.local_updates.push(merged);
+                        result.merged.push(name);
+                        continue;
+                    }
+                    (false, true) => true,
+                    (true, false) => false,
+                    _ => (r.updated_at, &r.device_id) > (l.updated_at, &l.device_id),
+                };
+                if take_remote {
+                    result.local_updates.push(r);
+                    result.pulled.push(name);
+                } else {
+                    result.pushed.push(name);
+                }
+            }
+            (None, None) => {}
+        }
+    }
+
+    result
+}
+
+fn merge_fields(mut local: Word, remote: &Word) -> Word {
+    if (remote.updated_at, &remote.device_id) > (local.updated_at, &local.device_id) {
+        local.meanings = remote.meanings.clone();
+    }
+    for e in remote.examples.lines() {
+        local.add_example(e);
+    }
+    for t in remote.tags.split(';') {
+        local.add_tag(t);
+    }
+    if remote.last_visit > local.last_visit {
+        local.period_days = remote.period_days;
+        local.last_visit = remote.last_visit;
+        local.next_visit = remote.next_visit;
+    }
+
+    local
+}
+
+#[cfg(test)]
+mod merge_tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    fn word(name: &str, meanings: &str, updated_at: i64, device_id: &str) -> Word {
+        let mut w = Word::from_name_and_meanings(name, meanings);
+        w.last_visit = Utc.timestamp(0, 0);
+        w.next_visit = w.last_visit + Duration::days(1);
+        w.updated_at = Utc.timestamp(updated_at, 0);
+        w.device_id = device_id.to_string();
+        w
+    }
+
+    #[test]
+    fn test_merge_words() {
+        let base = Some(Utc.timestamp(100, 0));
+        let now = Utc.timestamp(1000, 0);
+        let local = vec![
+            word("local-only", "a", 200, "l"),
+            word("same", "a", 50, "l"),
+            word("remote-newer", "a", 50, "l"),
+            word("local-newer", "b", 300, "l"),
+            word("both", "a", 200, "l"),
+        ];
+        let remote = vec![
+            word("remote-only", "a", 200, "r"),
+            word("same", "a", 50, "r"),
+            word("remote-newer", "b", 300, "r"),
+            word("local-newer", "a", 50, "r"),
+            word("both", "b", 300, "r"),
+        ];
+
+        let result = merge_words(local, remote, base, "l", now);
+        assert_eq!(result.pulled, vec!["remote-newer", "remote-only"]);
+        assert_eq!(result.pushed, vec!["local-newer", "local-only"]);
+        assert_eq!(result.merged, vec!["both"]);
+
+        let both = result
+            .local_updates
+            .iter()
+            .find(|w| w.name == "both")
+            .unwrap();
+        assert!(both.has_meanings("b"));
+        assert_eq!(both.updated_at, now);
+    }
+
+    #[test]
+    fn test_merge_tombstones() {
+        let base = Some(Utc.timestamp(100, 0));
+        let now = Utc.timestamp(1000, 0);
+        let mut deleted = word("deleted", "a", 300, "r");
+        deleted.deleted_at = Some(deleted.updated_at);
+        let mut stale = word("stale", "a", 200, "r");
+        stale.deleted_at = Some(stale.updated_at);
+        let local = vec![word("deleted", "a", 50, "l"), word("stale", "b", 250, "l")];
+        let remote = vec![deleted, stale];
+
+        let result = merge_words(local, remote, base, "l", now);
+        assert_eq!(result.pulled, vec!["deleted"]);
+        assert_eq!(result.pushed, vec!["stale"]);
+        assert!(result.merged.is_empty());
+        assert!(result.local_updates[0].deleted_at.is_some());
+    }
+
+    #[test]
+    fn test_merge_conflict_rules() {
+        let now = Utc.timestamp(1000, 0);
+
+        // unchanged on both sides since the base, yet different: the newer one is kept
+        let base = Some(Utc.timestamp(500, 0));
+        let local = vec![word("older", "a", 100, "l"), word("newer", "a", 300, "l")];
+        let remote = vec![word("older", "b", 200, "r"), word("newer", "b", 200, "r")];
+        let result = merge_words(local, remote, base, "l", now);
+        assert_eq!(result.pulled, vec!["older"]);
+        assert_eq!(result.pushed, vec!["newer"]);
+        assert!(result.merged.is_empty());
+
+        // without a base every difference is a conflict, merged unless it involves a deletion
+        let mut deleted = word("deleted", "a", 100, "r");
+        deleted.deleted_at = Some(deleted.updated_at);
+        let local = vec![word("both", "a", 100, "l"), word("deleted", "b", 200, "l")];
+        let remote = vec![word("both", "b", 200, "r"), deleted];
+        let result = merge_words(local, remote, None, "l", now);
+        assert_eq!(result.merged, vec!["both"]);
+        assert_eq!(result.pushed, vec!["deleted"]);
+        assert_eq!(result.local_updates.len(), 1);
+        assert!(result.local_updates[0].has_meanings("b"));
+
+        // a meaning deleted on one side stays deleted though the other side changed the word
+        let mut local = word("edited", "a;b", 200, "l");
+        local.add_tag("local");
+        let mut remote = word("edited", "a", 300, "r");
+        remote.add_tag("remote");
+        let result = merge_words(vec![local], vec![remote], base, "l", now);
+        assert_eq!(result.merged, vec!["edited"]);
+        let edited = &result.local_updates[0];
+        assert!(edited.has_meanings("a"));
+        assert_eq!(edited.tags, "local;remote");
+    }
+}
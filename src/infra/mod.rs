@@ -1,13 +1,47 @@
 mod conf;
 mod consts;
 mod db;
+mod dir_backend;
+mod email_backend;
+#[cfg(test)]
+mod fake_mail_server;
+mod git_backend;
+mod kindle;
+mod mail;
+mod merge;
 mod model;
+mod oauth2;
+mod payload_crypto;
+mod proxy;
+mod secret;
 mod sql_value;
+mod sync_backend;
 mod sync_data;
+mod sync_error;
+mod tls;
+mod webdav_backend;
+mod word_text;
 
 pub use conf::*;
 pub use consts::*;
 pub use db::*;
+pub use dir_backend::*;
+pub use email_backend::*;
+#[cfg(test)]
+pub use fake_mail_server::*;
+pub use git_backend::*;
+pub use kindle::*;
+pub use mail::*;
+pub use merge::*;
 pub use model::*;
+pub use oauth2::*;
+pub use payload_crypto::*;
+pub use proxy::*;
+pub use secret::*;
 pub use sql_value::*;
+pub use sync_backend::*;
 pub use sync_data::*;
+pub use sync_error::*;
+pub use tls::*;
+pub use webdav_backend::*;
+pub use word_text::*;
@@ -16,6 +16,16 @@ pub struct Word {
     pub period_days: u16,
     pub last_visit: DateTime<Utc>,
     pub next_visit: DateTime<Utc>,
+    /// example sentences, one per line
+    pub examples: String,
+    /// tags separated by `;`
+    pub tags: String,
+    /// time of the last modification, used to merge words when syncing
+    pub updated_at: DateTime<Utc>,
+    /// id of the device on which the last modification happened
+    pub device_id: String,
+    /// time of deletion, if the word is only a tombstone kept for syncing
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 impl Word {
@@ -37,6 +47,8 @@ impl Word {
         cmp_map
     }
 
+    /// Make a new word due a day later. Words taken for study are rescheduled by the study
+    /// calendar, see `word_manager`.
     pub fn from_name_and_meanings(name: &str, meanings: &str) -> Word {
         let now = Utc::now();
         let period_days = 1;
@@ -48,6 +60,11 @@ impl Word {
             period_days,
             last_visit: now,
             next_visit: now + Duration::days(period_days as i64),
+            examples: String::new(),
+            tags: String::new(),
+            updated_at: now,
+            device_id: String::new(),
+            deleted_at: None,
         }
     }
 
@@ -66,6 +83,15 @@ impl Word {
                 NaiveDateTime::from_timestamp(get_val(&hash_map, "next_visit")?.unwrap(), 0),
                 Utc,
             ),
+            examples: get_val(&hash_map, "examples")?.unwrap_or_default(),
+            tags: get_val(&hash_map, "tags")?.unwrap_or_default(),
+            updated_at: DateTime::from_utc(
+                NaiveDateTime::from_timestamp(get_val(&hash_map, "updated_at")?.unwrap_or(0), 0),
+                Utc,
+            ),
+            device_id: get_val(&hash_map, "device_id")?.unwrap_or_default(),
+            deleted_at: get_val(&hash_map, "deleted_at")?
+                .map(|t| DateTime::from_utc(NaiveDateTime::from_timestamp(t, 0), Utc)),
         })
     }
 
@@ -101,6 +127,45 @@ impl Word {
 
         self_mset == mset
     }
+
+    /// Whether two versions of a word differ in anything but id and modification info.
+    pub fn content_eq(&self, other: &Word) -> bool {
+        let meaning_set =
+            |m: &str| -> HashSet<String> { Word::make_meaning_cmp_map(m).into_keys().collect() };
+
+        self.name == other.name
+            && meaning_set(&self.meanings) == meaning_set(&other.meanings)
+            && self.period_days == other.period_days
+            && self.last_visit == other.last_visit
+            && self.next_visit == other.next_visit
+            && self.examples == other.examples
+            && self.tags == other.tags
+            && self.deleted_at.is_some() == other.deleted_at.is_some()
+    }
+
+    pub fn add_example(&mut self, example: &str) {
+        let example = example.trim();
+        if example.is_empty() || self.examples.lines().any(|e| e == example) {
+            return;
+        }
+
+        if !self.examples.is_empty() {
+            self.examples.push('\n');
+        }
+        self.examples.push_str(example);
+    }
+
+    pub fn add_tag(&mut self, tag: &str) {
+        let tag = tag.trim().replace(';', ",");
+        if tag.is_empty() || self.tags.split(';').any(|t| t == tag) {
+            return;
+        }
+
+        if !self.tags.is_empty() {
+            self.tags.push(';');
+        }
+        self.tags.push_str(&tag);
+    }
 }
 
 fn to_hashmap<'a>(pairs: &'a [(&str, Option<&str>)]) -> HashMap<&'a str, Option<&'a str>> {
@@ -0,0 +1,424 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// OAuth2 client settings to get tokens for XOAUTH2 logins.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuth2Config {
+    pub client_id: String,
+    /// secret of an installed app, kept in the secret store instead of the config file
+    #[serde(skip)]
+    pub client_secret: Option<String>,
+    /// authorization endpoint, used with a loopback redirect
+    pub auth_url: String,
+    /// device authorization endpoint, preferred over `auth_url` if present
+    pub device_auth_url: Option<String>,
+    pub token_url: String,
+    pub scope: String,
+}
+
+impl OAuth2Config {
+    pub fn google(client_id: &str, client_secret: Option<&str>) -> OAuth2Config {
+        OAuth2Config {
+            client_id: client_id.to_string(),
+            client_secret: client_secret.map(|s| s.to_string()),
+            auth_url: "https://accounts.google.com/o/oauth2/v2/auth".to_string(),
+            // Google does not allow the mail scope in device flow
+            device_auth_url: None,
+            token_url: "https://oauth2.googleapis.com/token".to_string(),
+            scope: "https://mail.google.com/".to_string(),
+        }
+    }
+
+    pub fn microsoft(client_id: &str, client_secret: Option<&str>) -> OAuth2Config {
+        let base_url = "https://login.microsoftonline.com/common/oauth2/v2.0";
+        OAuth2Config {
+            client_id: client_id.to_string(),
+            client_secret: client_secret.map(|s| s.to_string()),
+            auth_url: format!("{}/authorize", base_url),
+            device_auth_url: Some(format!("{}/devicecode", base_url)),
+            token_url: format!("{}/token", base_url),
+            scope: "offline_access https://outlook.office.com/IMAP.AccessAsUser.All https://outlook.office.com/SMTP.Send".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceCodeResponse {
+    device_code: String,
+    user_code: String,
+    #[serde(alias = "verification_url")]
+    verification_uri: String,
+    expires_in: u64,
+    interval: Option<u64>,
+}
+
+/// Post a form to the token endpoint. OAuth2 errors are returned as `Ok(Err(error_code))`.
+fn post_token_form(
+    oauth2_config: &OAuth2Config,
+    form: &[(&str, &str)],
+) -> Result<std::result::Result<TokenResponse, String>> {
+    let mut form = form.to_vec();
+    form.push(("client_id", &oauth2_config.client_id));
+    if let Some(client_secret) = &oauth2_config.client_secret {
+        form.push(("client_secret", client_secret));
+    }
+
+    match ureq::post(&oauth2_config.token_url).send_form(&form) {
+        Ok(response) => Ok(Ok(serde_json::from_str(&response.into_string()?)?)),
+        Err(ureq::Error::Status(_, response)) => {
+            let body = response.into_string()?;
+            let error: ErrorResponse = serde_json::from_str(&body)
+                .map_err(|_| Error::msg(format!("OAuth2 token request failed: {}", body)))?;
+            Ok(Err(error.error))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Get a new access token with the refresh token.
+pub fn refresh_access_token(
+    oauth2_config: &OAuth2Config,
+    refresh_token: &str,
+) -> Result<TokenResponse> {
+    post_token_form(
+        oauth2_config,
+        &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ],
+    )?
+    .map_err(|e| {
+        Error::msg(format!(
+            "Failed to refresh OAuth2 token: {}. Please sign in again.",
+            e
+        ))
+    })
+}
+
+/// Let user authorize in browser, and get the tokens.
+pub fn authorize(oauth2_config: &OAuth2Config) -> Result<TokenResponse> {
+    match &oauth2_config.device_auth_url {
+        Some(device_auth_url) => authorize_by_device_code(oauth2_config, device_auth_url),
+        None => authorize_by_loopback(oauth2_config),
+    }
+}
+
+fn authorize_by_device_code(
+    oauth2_config: &OAuth2Config,
+    device_auth_url: &str,
+) -> Result<TokenResponse> {
+    let response = ureq::post(device_auth_url).send_form(&[
+        ("client_id", &oauth2_config.client_id),
+        ("scope", &oauth2_config.scope),
+    ])?;
+    let device_code: DeviceCodeResponse = serde_json::from_str(&response.into_string()?)?;
+    println!(
+        "Open {} and enter the code {} to authorize wordmem.",
+        device_code.verification_uri, device_code.user_code
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(device_code.expires_in);
+    let mut interval = Duration::from_secs(device_code.interval.unwrap_or(5));
+    while Instant::now() < deadline {
+        thread::sleep(interval);
+        let result = post_token_form(
+            oauth2_config,
+            &[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+                ("device_code", &device_code.device_code),
+            ],
+        )?;
+        match result {
+            Ok(token) => return Ok(token),
+            Err(e) if e == "authorization_pending" => {}
+            Err(e) if e == "slow_down" => interval += Duration::from_secs(5),
+            Err(e) => return Err(Error::msg(format!("Authorization failed: {}", e))),
+        }
+    }
+
+    Err(Error::msg("Authorization timed out."))
+}
+
+fn authorize_by_loopback(oauth2_config: &OAuth2Config) -> Result<TokenResponse> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let redirect_uri = format!("http://{}", listener.local_addr()?);
+    let state = hex::encode(rand::random::<[u8; 16]>());
+    let code_verifier = make_code_verifier();
+    let url = format!(
+        "{}?response_type=code&access_type=offline&prompt=consent&client_id={}&redirect_uri={}&scope={}&state={}&code_challenge={}&code_challenge_method=S256",
+        oauth2_config.auth_url,
+        encode_url_component(&oauth2_config.client_id),
+        encode_url_component(&redirect_uri),
+        encode_url_component(&oauth2_config.scope),
+        state,
+        get_code_challenge(&code_verifier)
+    );
+    println!("Open the URL below to authorize wordmem, if the browser does not open it:");
+    println!("{}", url);
+    let _ = open::that(&url);
+
+    let code = receive_auth_code(&listener, &state)?;
+    exchange_auth_code(oauth2_config, &code, &redirect_uri, &code_verifier)
+}
+
+/// Wait for the browser to be redirected back, and get the authorization code.
+fn receive_auth_code(listener: &TcpListener, state: &str) -> Result<String> {
+    let (mut stream, _) = listener.accept()?;
+    let mut request_line = String::new();
+    BufReader::new(stream.try_clone()?).read_line(&mut request_line)?;
+    let target = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let query = parse_query(target.split_once('?').map(|(_, q)| q).unwrap_or_default());
+    let param = |name: &str| {
+        query
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.clone())
+    };
+    let message = if param("code").is_some() {
+        "Authorized. You can close this page now."
+    } else {
+        "Authorization failed."
+    };
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        message.len(),
+        message
+    )?;
+
+    if param("state").as_deref() != Some(state) {
+        return Err(Error::msg("Authorization failed: state mismatch."));
+    }
+    param("code").ok_or_else(|| {
+        Error::msg(format!(
+            "Authorization failed: {}",
+            param("error").unwrap_or_default()
+        ))
+    })
+}
+
+fn exchange_auth_code(
+    oauth2_config: &OAuth2Config,
+    code: &str,
+    redirect_uri: &str,
+    code_verifier: &str,
+) -> Result<TokenResponse> {
+    post_token_form(
+        oauth2_config,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("code_verifier", code_verifier),
+        ],
+    )?
+    .map_err(|e| Error::msg(format!("Authorization failed: {}", e)))
+}
+
+/// Make a PKCE code verifier of 43 URL-safe characters.
+fn make_code_verifier() -> String {
+    base64::encode_config(rand::random::<[u8; 32]>(), base64::URL_SAFE_NO_PAD)
+}
+
+/// Get the S256 PKCE code challenge of the verifier.
+fn get_code_challenge(code_verifier: &str) -> String {
+    base64::encode_config(
+        Sha256::digest(code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// Build the initial client response of SASL XOAUTH2.
+pub fn build_xoauth2_string(user: &str, access_token: &str) -> String {
+    format!("user={}\x01auth=Bearer {}\x01\x01", user, access_token)
+}
+
+fn encode_url_component(s: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded += &format!("%{:02X}", b),
+        }
+    }
+
+    encoded
+}
+
+fn decode_url_component(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(b) => {
+                        decoded.push(b);
+                        i += 2;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (k, v) = p.split_once('=').unwrap_or((p, ""));
+            (decode_url_component(k), decode_url_component(v))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod oauth2_tests {
+    use std::{
+        io::Read,
+        net::TcpStream,
+        sync::mpsc::{self, Receiver},
+    };
+
+    use super::*;
+    use crate::infra::serve_local;
+
+    /// Serve token requests with the status and body, and pass on the forms posted.
+    fn serve_token_request(status: u16, body: &'static str) -> (OAuth2Config, Receiver<String>) {
+        let (sender, receiver) = mpsc::channel();
+        let port = serve_local(move |stream| {
+            let mut reader = BufReader::new(stream.try_clone()?);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((k, v)) = line.split_once(':') {
+                    if k.eq_ignore_ascii_case("content-length") {
+                        content_length = v.trim().parse()?;
+                    }
+                }
+            }
+            let mut form = vec![0; content_length];
+            reader.read_exact(&mut form)?;
+            write!(
+                &stream,
+                "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )?;
+            sender.send(String::from_utf8(form)?)?;
+            Ok(())
+        })
+        .unwrap();
+        let mut config = OAuth2Config::google("id", Some("secret"));
+        config.token_url = format!("http://127.0.0.1:{}/token", port);
+
+        (config, receiver)
+    }
+
+    #[test]
+    fn test_url_components() {
+        let s = "https://mail.google.com/ a+b";
+        assert_eq!(decode_url_component(&encode_url_component(s)), s);
+
+        let query = parse_query("state=abc&code=4%2F0Ab&scope=a+b");
+        assert_eq!(query[1], ("code".to_string(), "4/0Ab".to_string()));
+        assert_eq!(query[2].1, "a b");
+    }
+
+    #[test]
+    fn test_code_challenge() {
+        // the example of RFC 7636 appendix B
+        assert_eq!(
+            get_code_challenge("dBjftJeZ4CVP-mJ0kwRgZKg4xpdiWOwRc_EnCg3yT7Y"),
+            "E9Melhoa2OwvFrEMTJguCQaoWbGbiKTu8XyXwZiMOR0"
+        );
+        assert_eq!(make_code_verifier().len(), 43);
+    }
+
+    #[test]
+    fn test_refresh_access_token() {
+        let (config, forms) =
+            serve_token_request(200, r#"{"access_token":"at","expires_in":3600}"#);
+        let token = refresh_access_token(&config, "rt").unwrap();
+        assert_eq!(token.access_token, "at");
+        assert!(token.refresh_token.is_none());
+        let form = parse_query(&forms.recv().unwrap());
+        assert!(form.contains(&("grant_type".to_string(), "refresh_token".to_string())));
+        assert!(form.contains(&("refresh_token".to_string(), "rt".to_string())));
+        assert!(form.contains(&("client_secret".to_string(), "secret".to_string())));
+
+        let (config, forms) = serve_token_request(400, r#"{"error":"invalid_grant"}"#);
+        let error = refresh_access_token(&config, "rt").unwrap_err();
+        assert!(error.to_string().contains("invalid_grant"));
+        forms.recv().unwrap();
+    }
+
+    #[test]
+    fn test_authorize_by_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let browse = |target: &'static str| {
+            thread::spawn(move || {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, addr).unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                response
+            })
+        };
+
+        let browser = browse("/?state=other&code=c");
+        assert!(receive_auth_code(&listener, "s").is_err());
+        browser.join().unwrap();
+
+        let browser = browse("/?state=s&code=4%2F0Ab");
+        let code = receive_auth_code(&listener, "s").unwrap();
+        assert_eq!(code, "4/0Ab");
+        assert!(browser.join().unwrap().contains("Authorized."));
+
+        let (config, forms) = serve_token_request(
+            200,
+            r#"{"access_token":"at","refresh_token":"rt","expires_in":3600}"#,
+        );
+        let token = exchange_auth_code(&config, &code, "http://127.0.0.1", "verifier").unwrap();
+        assert_eq!(token.refresh_token.as_deref(), Some("rt"));
+        let form = parse_query(&forms.recv().unwrap());
+        assert!(form.contains(&("code".to_string(), "4/0Ab".to_string())));
+        assert!(form.contains(&("code_verifier".to_string(), "verifier".to_string())));
+    }
+}
@@ -0,0 +1,175 @@
+use anyhow::{Error, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, NewAead, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+
+const MAGIC: &[u8] = b"WMENC";
+const FORMAT_VERSION: u8 = 1;
+const KDF_ARGON2ID: u8 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+/// magic, version, KDF id, 3 KDF params, salt, nonce
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4 * 3 + SALT_LEN + NONCE_LEN;
+
+/// Argon2id memory cost in KiB.
+const M_COST: u32 = 19 * 1024;
+const T_COST: u32 = 2;
+const P_COST: u32 = 1;
+/// Refuse headers asking for more memory than this, in KiB, so that bad data cannot exhaust it.
+const MAX_M_COST: u32 = 1024 * 1024;
+/// Refuse headers asking for more passes or lanes than these, so that bad data cannot pin the CPU.
+const MAX_T_COST: u32 = 10;
+const MAX_P_COST: u32 = 16;
+
+/// Tell whether the bytes are a payload made by `encrypt_payload`.
+pub fn is_encrypted_payload(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Encrypt bytes with a key derived from the passphrase by Argon2id, using XChaCha20-Poly1305.
+/// The header, carrying format version and KDF parameters, is authenticated as well.
+pub fn encrypt_payload(plain_bytes: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let salt: [u8; SALT_LEN] = rand::random();
+    let nonce: [u8; NONCE_LEN] = rand::random();
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.push(FORMAT_VERSION);
+    header.push(KDF_ARGON2ID);
+    for param in [M_COST, T_COST, P_COST] {
+        header.extend_from_slice(&param.to_le_bytes());
+    }
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&nonce);
+
+    let key = derive_key(passphrase, &salt, M_COST, T_COST, P_COST)?;
+    let cipher_bytes = XChaCha20Poly1305::new(Key::from_slice(&key))
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: plain_bytes,
+                aad: &header,
+            },
+        )
+        .map_err(|_| Error::msg("Failed to encrypt sync data."))?;
+
+    let mut bytes = header;
+    bytes.extend_from_slice(&cipher_bytes);
+    Ok(bytes)
+}
+
+/// Decrypt a payload made by `encrypt_payload`.
+pub fn decrypt_payload(bytes: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    if !is_encrypted_payload(bytes) || bytes.len() < HEADER_LEN {
+        return Err(Error::msg("Sync data is not encrypted by wordmem."));
+    }
+
+    let (header, cipher_bytes) = bytes.split_at(HEADER_LEN);
+    let mut pos = MAGIC.len();
+    if header[pos] != FORMAT_VERSION {
+        return Err(Error::msg(format!(
+            "Sync data is encrypted in format version {}, which this wordmem does not support. Please upgrade wordmem.",
+            header[pos]
+        )));
+    }
+    pos += 1;
+    if header[pos] != KDF_ARGON2ID {
+        return Err(Error::msg(format!(
+            "Sync data is encrypted with unknown key derivation function {}.",
+            header[pos]
+        )));
+    }
+    pos += 1;
+
+    let mut params = [0; 3];
+    for param in params.iter_mut() {
+        let mut param_bytes = [0; 4];
+        param_bytes.copy_from_slice(&header[pos..pos + 4]);
+        *param = u32::from_le_bytes(param_bytes);
+        pos += 4;
+    }
+    let [m_cost, t_cost, p_cost] = params;
+    if m_cost > MAX_M_COST {
+        return Err(Error::msg("Sync data asks for too much memory to decrypt."));
+    }
+    if t_cost > MAX_T_COST || p_cost > MAX_P_COST {
+        return Err(Error::msg(
+            "Sync data asks for too much computation to decrypt.",
+        ));
+    }
+    let salt = &header[pos..pos + SALT_LEN];
+    pos += SALT_LEN;
+    let nonce = &header[pos..pos + NONCE_LEN];
+
+    let key = derive_key(passphrase, salt, m_cost, t_cost, p_cost)?;
+    XChaCha20Poly1305::new(Key::from_slice(&key))
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: cipher_bytes,
+                aad: header,
+            },
+        )
+        .map_err(|_| {
+            Error::msg("Failed to decrypt sync data. The sync passphrase is wrong, or the data is corrupted.")
+        })
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+) -> Result<[u8; KEY_LEN]> {
+    let params = Params::new(m_cost, t_cost, p_cost, Some(KEY_LEN))
+        .map_err(|e| Error::msg(format!("Invalid key derivation parameters: {}", e)))?;
+    let mut key = [0; KEY_LEN];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| Error::msg(format!("Failed to derive key from passphrase: {}", e)))?;
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod payload_crypto_tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_payload_crypto() -> Result<()> {
+        let bytes = encrypt_payload(b"some words", "passphrase")?;
+        assert!(is_encrypted_payload(&bytes));
+        assert_eq!(
+            decrypt_payload(&bytes, "passphrase")?,
+            b"some words".to_vec()
+        );
+        assert!(decrypt_payload(&bytes, "wrong passphrase").is_err());
+
+        // the header is authenticated too
+        let mut tampered = bytes.clone();
+        tampered[MAGIC.len() + 2] ^= 1;
+        assert!(decrypt_payload(&tampered, "passphrase").is_err());
+
+        // costs beyond the limits are refused before deriving any key
+        for (i, cost) in [
+            (0, MAX_M_COST + 1),
+            (1, MAX_T_COST + 1),
+            (2, MAX_P_COST + 1),
+        ] {
+            let mut costly = bytes.clone();
+            let pos = MAGIC.len() + 2 + 4 * i;
+            costly[pos..pos + 4].copy_from_slice(&cost.to_le_bytes());
+            let error = decrypt_payload(&costly, "passphrase").unwrap_err();
+            assert!(error.to_string().starts_with("Sync data asks for too much"));
+        }
+
+        Ok(())
+    }
+}
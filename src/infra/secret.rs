@@ -0,0 +1,430 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::{Debug, Display, Formatter},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use anyhow::{Error, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::infra::{decrypt_payload, encrypt_payload, AppConfig, KEYRING_SERVICE};
+
+/// Environment variable giving the password of the secret file, instead of asking for it.
+pub const SECRET_FILE_PASSWORD_ENV: &str = "WORDMEM_SECRETS_PASSWORD";
+/// Account looked up to tell whether system keyring is usable.
+const KEYRING_PROBE_ACCOUNT: &str = "probe";
+
+/// Where secrets such as passwords and refresh tokens are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SecretStore {
+    /// system keyring, e.g. Secret Service, macOS Keychain or Windows Credential Manager
+    Keyring,
+    /// a file in the config dir encrypted with a password, for systems without a keyring service
+    File,
+}
+
+impl Display for SecretStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SecretStore::Keyring => "keyring",
+            SecretStore::File => "file",
+        })
+    }
+}
+
+impl FromStr for SecretStore {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "keyring" => Ok(SecretStore::Keyring),
+            "file" => Ok(SecretStore::File),
+
+            _ => Err(Self::Err::msg(format!("Unrecognized secret store: {}", s))),
+        }
+    }
+}
+
+/// Handle to the secret store of a config, kept by `AppConfig` and shared with the auto sync jobs
+/// of a command, so that the secret file password is asked for at most once.
+#[derive(Default)]
+pub struct Secrets {
+    /// store chosen by the config, or detected on first use if absent
+    store: Mutex<Option<SecretStore>>,
+    /// password of the secret file, once asked for
+    file_password: Mutex<Option<String>>,
+}
+
+impl Debug for Secrets {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Secrets")
+            .field("store", &self.store.lock().unwrap())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Secrets {
+    /// Use the store for secrets. `None` uses system keyring if available, or the secret file
+    /// otherwise.
+    pub fn new(store: Option<SecretStore>) -> Secrets {
+        Secrets {
+            store: Mutex::new(store),
+            file_password: Mutex::new(None),
+        }
+    }
+
+    fn get_store(&self) -> SecretStore {
+        *self
+            .store
+            .lock()
+            .unwrap()
+            .get_or_insert_with(detect_secret_store)
+    }
+}
+
+/// Use system keyring if available, or the secret file otherwise.
+fn detect_secret_store() -> SecretStore {
+    let probe = keyring::Entry::new(KEYRING_SERVICE, KEYRING_PROBE_ACCOUNT).get_password();
+    if let Err(keyring::Error::PlatformFailure(_) | keyring::Error::NoStorageAccess(_)) = probe {
+        println!("System keyring unavailable. Secrets are kept in an encrypted file instead.");
+        SecretStore::File
+    } else {
+        SecretStore::Keyring
+    }
+}
+
+/// Get a secret of the account from system keyring or the secret file.
+pub fn get_secret(secrets: &Secrets, account: &str) -> Result<Option<String>> {
+    get_secret_from(secrets, secrets.get_store(), account)
+}
+
+pub fn set_secret(secrets: &Secrets, account: &str, secret: &str) -> Result<()> {
+    set_secret_to(secrets, secrets.get_store(), account, secret)
+}
+
+pub fn clear_secret(secrets: &Secrets, account: &str) -> Result<()> {
+    clear_secret_from(secrets, secrets.get_store(), account)
+}
+
+/// Move the secrets of the accounts to the other store, returning how many were moved.
+/// Accounts without a secret are skipped.
+pub fn migrate_secrets(secrets: &Secrets, accounts: &[String], to: SecretStore) -> Result<usize> {
+    let from = secrets.get_store();
+    if from == to {
+        return Ok(0);
+    }
+
+    let moved = move_secrets(
+        accounts,
+        |account| get_secret_from(secrets, from, account),
+        |account, secret| set_secret_to(secrets, to, account, secret),
+        |account| clear_secret_from(secrets, from, account),
+    )?;
+    *secrets.store.lock().unwrap() = Some(to);
+
+    Ok(moved)
+}
+
+/// Move secrets of the accounts by the functions of the two stores, each cleared from the old
+/// store only once kept in the new one.
+fn move_secrets<G, S, C>(accounts: &[String], get: G, set: S, clear: C) -> Result<usize>
+where
+    G: Fn(&str) -> Result<Option<String>>,
+    S: Fn(&str, &str) -> Result<()>,
+    C: Fn(&str) -> Result<()>,
+{
+    let mut moved = 0;
+    for account in accounts {
+        if let Some(secret) = get(account)? {
+            set(account, &secret)?;
+            clear(account)?;
+            moved += 1;
+        }
+    }
+
+    Ok(moved)
+}
+
+/// Ask for the password of the secret file now, if it is in use, so that it is not asked for
+/// later by another thread, such as an auto sync job which may be given up waiting for.
+pub fn unlock_secret_store(secrets: &Secrets) -> Result<()> {
+    let secret_file = SecretFile::new(SecretFile::get_default_path());
+    if secrets.get_store() == SecretStore::File && secret_file.exists() {
+        secret_file.load(&read_secret_file_password(secrets, false)?)?;
+    }
+
+    Ok(())
+}
+
+fn get_secret_from(secrets: &Secrets, store: SecretStore, account: &str) -> Result<Option<String>> {
+    match store {
+        SecretStore::Keyring => {
+            let secret = keyring::Entry::new(KEYRING_SERVICE, account).get_password();
+            if let Err(keyring::Error::NoEntry) = secret {
+                return Ok(None);
+            }
+
+            Ok(Some(secret?))
+        }
+        SecretStore::File => {
+            let secret_file = SecretFile::new(SecretFile::get_default_path());
+            if !secret_file.exists() {
+                return Ok(None);
+            }
+            secret_file.get(account, &read_secret_file_password(secrets, false)?)
+        }
+    }
+}
+
+fn set_secret_to(secrets: &Secrets, store: SecretStore, account: &str, secret: &str) -> Result<()> {
+    match store {
+        SecretStore::Keyring => {
+            keyring::Entry::new(KEYRING_SERVICE, account).set_password(secret)?;
+            Ok(())
+        }
+        SecretStore::File => {
+            let secret_file = SecretFile::new(SecretFile::get_default_path());
+            let password = read_secret_file_password(secrets, !secret_file.exists())?;
+            secret_file.set(account, secret, &password)
+        }
+    }
+}
+
+fn clear_secret_from(secrets: &Secrets, store: SecretStore, account: &str) -> Result<()> {
+    match store {
+        SecretStore::Keyring => {
+            let result = keyring::Entry::new(KEYRING_SERVICE, account).delete_password();
+            if let Err(keyring::Error::NoEntry) = result {
+                return Ok(());
+            }
+
+            result?;
+            Ok(())
+        }
+        SecretStore::File => {
+            let secret_file = SecretFile::new(SecretFile::get_default_path());
+            if !secret_file.exists() {
+                return Ok(());
+            }
+            secret_file.clear(account, &read_secret_file_password(secrets, false)?)
+        }
+    }
+}
+
+/// Get the password of the secret file from environment or user, asking twice for a new file.
+fn read_secret_file_password(secrets: &Secrets, new_file: bool) -> Result<String> {
+    let mut cached = secrets.file_password.lock().unwrap();
+    if let Some(password) = cached.as_ref() {
+        return Ok(password.clone());
+    }
+
+    let password = match env::var(SECRET_FILE_PASSWORD_ENV) {
+        Ok(password) => password,
+        Err(_) => {
+            let password = rpassword::prompt_password("Enter secret file password: ")?;
+            if new_file
+                && rpassword::prompt_password("Enter secret file password again: ")? != password
+            {
+                return Err(Error::msg("Passwords do not match."));
+            }
+            password
+        }
+    };
+    if password.is_empty() {
+        return Err(Error::msg("Secret file password must not be empty."));
+    }
+
+    *cached = Some(password.clone());
+    Ok(password)
+}
+
+/// Secrets of accounts kept as JSON, encrypted the same way as sync payloads.
+pub struct SecretFile {
+    path: PathBuf,
+}
+
+impl SecretFile {
+    pub fn new(path: PathBuf) -> SecretFile {
+        SecretFile { path }
+    }
+
+    pub fn get_default_path() -> PathBuf {
+        let mut path = AppConfig::get_default_conf_dir();
+        path.push("secrets.enc");
+
+        path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    fn load(&self, password: &str) -> Result<BTreeMap<String, String>> {
+        if !self.exists() {
+            return Ok(BTreeMap::new());
+        }
+
+        let plain_bytes = decrypt_payload(&fs::read(&self.path)?, password).map_err(|_| {
+            Error::msg(format!(
+                "Failed to open secret file {}. Wrong password?",
+                self.path.display()
+            ))
+        })?;
+        Ok(serde_json::from_slice(&plain_bytes)?)
+    }
+
+    /// Write the secrets, or remove the file once none is left.
+    fn save(&self, secrets: &BTreeMap<String, String>, password: &str) -> Result<()> {
+        if secrets.is_empty() {
+            if self.exists() {
+                fs::remove_file(&self.path)?;
+            }
+            return Ok(());
+        }
+
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(dir)?;
+        let bytes = encrypt_payload(&serde_json::to_vec(secrets)?, password)?;
+
+        // write to a temporary file readable by the owner only, and put it in place of the old
+        // one, so that the secrets are neither left half-written nor readable by others
+        let mut tmp_file = tempfile::NamedTempFile::new_in(dir)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tmp_file
+                .as_file()
+                .set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        tmp_file.write_all(&bytes)?;
+        tmp_file.as_file().sync_all()?;
+        tmp_file.persist(&self.path)?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, account: &str, password: &str) -> Result<Option<String>> {
+        Ok(self.load(password)?.remove(account))
+    }
+
+    pub fn set(&self, account: &str, secret: &str, password: &str) -> Result<()> {
+        let mut secrets = self.load(password)?;
+        secrets.insert(account.to_string(), secret.to_string());
+        self.save(&secrets, password)
+    }
+
+    pub fn clear(&self, account: &str, password: &str) -> Result<()> {
+        let mut secrets = self.load(password)?;
+        if secrets.remove(account).is_some() {
+            self.save(&secrets, password)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod secret_tests {
+    use std::cell::RefCell;
+
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_secret_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let secret_file = SecretFile::new(dir.path().join("secrets.enc"));
+        assert_eq!(secret_file.get("someone@example.com", "password")?, None);
+
+        secret_file.set("someone@example.com", "secret", "password")?;
+        secret_file.set("sync-passphrase", "passphrase", "password")?;
+        assert!(!fs::read(dir.path().join("secrets.enc"))?
+            .windows(6)
+            .any(|w| w == b"secret"));
+        assert_eq!(
+            secret_file
+                .get("someone@example.com", "password")?
+                .as_deref(),
+            Some("secret")
+        );
+        assert!(secret_file.get("someone@example.com", "wrong").is_err());
+
+        secret_file.clear("someone@example.com", "password")?;
+        assert_eq!(secret_file.get("someone@example.com", "password")?, None);
+        secret_file.clear("sync-passphrase", "password")?;
+        assert!(!secret_file.exists());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_secret_file_mode() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("secrets.enc");
+        let secret_file = SecretFile::new(path.clone());
+        secret_file.set("someone@example.com", "secret", "password")?;
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+
+        // an existing file made readable by others is replaced by one which is not
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644))?;
+        secret_file.set("sync-passphrase", "passphrase", "password")?;
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_secrets() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let from = SecretFile::new(dir.path().join("secrets.enc"));
+        from.set("someone@example.com", "secret", "password")?;
+        from.set("sync-passphrase", "passphrase", "password")?;
+        let to = RefCell::new(BTreeMap::new());
+        let accounts = [
+            "someone@example.com".to_string(),
+            "oauth2:someone@example.com".to_string(),
+            "sync-passphrase".to_string(),
+        ];
+
+        // a secret not kept by the new store is left in the old one
+        let moved = move_secrets(
+            &accounts,
+            |account| from.get(account, "password"),
+            |account, _| Err(Error::msg(format!("Failed to keep {}", account))),
+            |account| from.clear(account, "password"),
+        );
+        assert!(moved.is_err());
+        assert!(from.get("someone@example.com", "password")?.is_some());
+
+        let moved = move_secrets(
+            &accounts,
+            |account| from.get(account, "password"),
+            |account, secret| {
+                to.borrow_mut()
+                    .insert(account.to_string(), secret.to_string());
+                Ok(())
+            },
+            |account| from.clear(account, "password"),
+        )?;
+        assert_eq!(moved, 2);
+        assert_eq!(
+            to.borrow().get("sync-passphrase").map(|s| s.as_str()),
+            Some("passphrase")
+        );
+        assert!(!from.exists());
+
+        Ok(())
+    }
+}
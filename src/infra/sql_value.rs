@@ -1,7 +1,6 @@
 use std::fmt::{Display, Formatter};
 
 pub enum SqlVal<'a> {
-    #[allow(dead_code)]
     Null,
     Integer(i64),
     #[allow(dead_code)]
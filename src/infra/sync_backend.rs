@@ -0,0 +1,168 @@
+use anyhow::{Error, Result};
+use chrono::{DateTime, Utc};
+
+use crate::infra::{
+    DeltaInfo, DirBackend, EmailBackend, GitBackend, GitConfig, PayloadFormat, Secrets, SyncConfig,
+    SyncData, WebDavBackend,
+};
+
+/// A snapshot of data stored at a sync backend.
+#[derive(Debug)]
+pub struct SnapshotInfo {
+    /// backend-specific id of the snapshot
+    pub id: String,
+    pub data_time: DateTime<Utc>,
+    /// size of the payload in bytes, if known
+    pub size: Option<u64>,
+    /// `None` for a full snapshot
+    pub delta: Option<DeltaInfo>,
+}
+
+/// A place to store snapshots of data for syncing.
+pub trait SyncBackend {
+    /// List available snapshots, newest first.
+    fn list_snapshots(&mut self) -> Result<Vec<SnapshotInfo>>;
+
+    /// List snapshots which may be new since the last pull, newest first.
+    /// Backends that remember where they stopped may list fewer than `list_snapshots`.
+    fn list_unseen_snapshots(&mut self) -> Result<Vec<SnapshotInfo>> {
+        self.list_snapshots()
+    }
+
+    /// Have a snapshot listed by `list_unseen_snapshots`, which could not be merged, listed again
+    /// by the next call, along with those after it.
+    fn keep_unseen_snapshot(&mut self, _snapshot: &SnapshotInfo) {}
+
+    /// Fetch a snapshot listed before.
+    fn fetch(&mut self, snapshot: &SnapshotInfo) -> Result<SyncData>;
+
+    /// Fetch the newest snapshot, if any.
+    fn fetch_latest(&mut self) -> Result<Option<SyncData>> {
+        match self.list_snapshots()?.first() {
+            Some(latest) => Ok(Some(self.fetch(latest)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Upload a new snapshot.
+    fn upload(&mut self, data: &SyncData) -> Result<()>;
+
+    /// Build payloads uploaded in the format. Backends not keeping payloads ignore it.
+    fn set_payload_format(&mut self, _format: PayloadFormat) {}
+
+    /// Read payloads not encrypted even though a passphrase is given, to merge snapshots pushed
+    /// before encryption was enabled. Backends not keeping payloads ignore it.
+    fn set_allow_plaintext(&mut self, _allow: bool) {}
+
+    /// Tell whether delta snapshots can be uploaded, rather than only full ones.
+    fn supports_delta(&self) -> bool {
+        true
+    }
+
+    /// Delete the snapshots.
+    fn delete_snapshots(&mut self, snapshots: &[&SnapshotInfo]) -> Result<()>;
+
+    /// Delete all snapshots older than the newest `keep` full ones, at least one of which is kept.
+    /// Returns the count deleted.
+    fn prune(&mut self, keep: usize) -> Result<usize> {
+        let snapshots = self.list_snapshots()?;
+        let stale = select_stale_snapshots(&snapshots, keep);
+        if !stale.is_empty() {
+            self.delete_snapshots(&stale)?;
+        }
+
+        Ok(stale.len())
+    }
+
+    /// Get the sync config with the state learned by the backend to be saved, if it changed.
+    fn get_updated_config(&self) -> Option<SyncConfig> {
+        None
+    }
+}
+
+/// Select snapshots older than the newest `keep` full ones, from snapshots sorted newest first.
+/// Deltas are kept along with the full snapshot they follow, since peers may not have seen them.
+pub fn select_stale_snapshots(snapshots: &[SnapshotInfo], keep: usize) -> Vec<&SnapshotInfo> {
+    let mut full_count = 0;
+    let mut stale = Vec::new();
+    for s in snapshots.iter() {
+        if full_count >= keep.max(1) {
+            stale.push(s);
+        } else if s.delta.is_none() {
+            full_count += 1;
+        }
+    }
+
+    stale
+}
+
+/// Open the backend of the sync config, with its login from the secrets. Payloads are encrypted
+/// if a passphrase is given.
+pub fn open_sync_backend<'a>(
+    sync_config: &'a SyncConfig,
+    passphrase: Option<String>,
+    secrets: &Secrets,
+) -> Result<Box<dyn SyncBackend + 'a>> {
+    Ok(match sync_config {
+        SyncConfig::Email(email_config) => {
+            Box::new(EmailBackend::new(email_config, passphrase, secrets)?)
+        }
+        SyncConfig::WebDav(webdav_config) => Box::new(WebDavBackend::new(
+            &webdav_config.webdav_url,
+            &webdav_config.webdav_username,
+            webdav_config.get_password(secrets)?,
+            passphrase,
+        )),
+        // the word list is kept readable there to be diffed, so it cannot be encrypted
+        SyncConfig::Git(_) if passphrase.is_some() => {
+            return Err(Error::msg(
+                "Encryption is not supported by the git sync backend.",
+            ))
+        }
+        SyncConfig::Git(git_config) => Box::new(GitBackend::new(
+            &git_config.git_repo,
+            &git_config.git_remote,
+            git_config
+                .git_branch
+                .as_deref()
+                .unwrap_or(GitConfig::DEFAULT_BRANCH),
+        )),
+        SyncConfig::Directory(dir_config) => Box::new(DirBackend::new(&dir_config.dir, passphrase)),
+    })
+}
+
+#[cfg(test)]
+mod sync_backend_tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    #[test]
+    fn test_select_stale_snapshots() {
+        let now = Utc.timestamp(1_600_000_000, 0);
+        // newest first: delta, full, delta, full, full
+        let snapshots: Vec<_> = [true, false, true, false, false]
+            .iter()
+            .enumerate()
+            .map(|(i, is_delta)| SnapshotInfo {
+                id: i.to_string(),
+                data_time: now - Duration::seconds(i as i64),
+                size: None,
+                delta: is_delta.then(|| DeltaInfo {
+                    device_id: "device".to_string(),
+                    seq: 1,
+                }),
+            })
+            .collect();
+
+        let ids = |keep| {
+            select_stale_snapshots(&snapshots, keep)
+                .iter()
+                .map(|s| s.id.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(1), vec!["2", "3", "4"]);
+        assert_eq!(ids(2), vec!["4"]);
+        assert!(ids(3).is_empty());
+    }
+}
@@ -1,202 +1,362 @@
 use std::io::{Read, Seek, SeekFrom, Write};
 
 use anyhow::{Error, Result};
-use bzip2::{read::BzDecoder, write::BzEncoder, Compression};
-use chrono::{DateTime, SecondsFormat, Utc};
-use lettre::{
-    self,
-    message::{header::ContentType, Attachment},
-    transport::smtp::authentication::Credentials,
-    SmtpTransport, Transport,
-};
-use mail_parser::{self, BodyPart};
+use bzip2::{read::BzDecoder, write::BzEncoder};
+use chrono::{DateTime, TimeZone, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use tar::Archive;
 
-use crate::infra::{Db, Encryption, SyncConfig, IMAP_ID_COMMAND};
+use crate::infra::{decrypt_payload, encrypt_payload, is_encrypted_payload, Db, SyncError};
+
+const SNAPSHOT_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+const SQLITE_HEADER_LEN: usize = 100;
+/// offset of `user_version` in SQLite DB header, used as schema version
+const USER_VERSION_OFFSET: usize = 60;
+/// limit of decompressed payloads, far above any word DB, against payloads crafted to expand
+/// without bound
+const MAX_DECOMPRESSED_LEN: u64 = 256 * 1024 * 1024;
+
+/// Compression of the tar archive in sync payloads. Payloads are told apart by their magic bytes
+/// when read, so that any of them can be pulled whatever is configured.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PayloadCompression {
+    Bzip2,
+    Gzip,
+    Zstd,
+}
+
+impl PayloadCompression {
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            PayloadCompression::Bzip2 => "bz2",
+            PayloadCompression::Gzip => "gz",
+            PayloadCompression::Zstd => "zst",
+        }
+    }
+
+    /// Tell the compression by the magic bytes the payload starts with.
+    fn detect(bytes: &[u8]) -> Option<PayloadCompression> {
+        if bytes.starts_with(b"BZh") {
+            Some(PayloadCompression::Bzip2)
+        } else if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(PayloadCompression::Gzip)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(PayloadCompression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// How to build sync payloads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PayloadFormat {
+    pub compression: PayloadCompression,
+    /// compression level, clamped to the range of the compression, its default if `None`
+    pub level: Option<i32>,
+}
+
+impl Default for PayloadFormat {
+    /// bzip2, which wordmem versions before other compressions can read
+    fn default() -> Self {
+        PayloadFormat {
+            compression: PayloadCompression::Bzip2,
+            level: None,
+        }
+    }
+}
+
+/// Marks a snapshot holding only the words changed on a device since its previous push.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeltaInfo {
+    pub device_id: String,
+    /// the last journal sequence number of the device included
+    pub seq: i64,
+}
 
 pub struct SyncData {
     pub data_time: DateTime<Utc>,
     pub db_bytes: Vec<u8>,
+    /// `None` for a full snapshot of all words
+    pub delta: Option<DeltaInfo>,
 }
 
 impl SyncData {
-    pub fn pull_data(sync_config: Option<&SyncConfig>) -> Result<Option<SyncData>> {
-        if sync_config.is_none() {
-            return Err(Error::msg("Sync config missing."));
-        }
+    /// Name of the payload file as mail attachment, carrying the compression.
+    pub fn get_payload_name(format: PayloadFormat) -> String {
+        format!(
+            "{}.tar.{}",
+            Db::get_default_db_name(),
+            format.compression.get_extension()
+        )
+    }
 
-        let sync_config = sync_config.unwrap();
-        let password = sync_config.get_password()?;
-        if password.is_none() {
-            return Err(Error::msg("Sync password missing."));
-        }
+    /// Name of the snapshot file for file-based backends, carrying the data time, delta info and
+    /// the compression.
+    pub fn get_snapshot_file_name(&self, format: PayloadFormat) -> String {
+        let delta = match &self.delta {
+            Some(delta) => format!("-delta-{}-{}", delta.device_id, delta.seq),
+            None => String::new(),
+        };
+        format!(
+            "wordmem-sync-{}{}.tar.{}",
+            self.data_time.format(SNAPSHOT_TIME_FORMAT),
+            delta,
+            format.compression.get_extension()
+        )
+    }
 
-        let password = password.unwrap();
-        let mut client = imap::ClientBuilder::new(
-            sync_config.imap_server_host.clone(),
-            sync_config.imap_server_port,
-        );
-        let client = match sync_config.imap_encryption {
-            Encryption::SslTls => &mut client,
-            Encryption::StartTls => client.starttls(),
-        }
-        .native_tls()?;
-        let mut imap_session = client
-            .login(&sync_config.email, &password)
-            .map_err(|e| e.0)?;
-
-        let caps = imap_session.capabilities()?;
-        if caps.has_str("ID") {
-            imap_session.run_command_and_check_ok(IMAP_ID_COMMAND)?;
+    /// Get the data time and delta info from a snapshot file name,
+    /// or `None` if it is not a snapshot file.
+    pub fn parse_snapshot_file_name(file_name: &str) -> Option<(DateTime<Utc>, Option<DeltaInfo>)> {
+        let regex = Regex::new(
+            r"^wordmem-sync-(?P<time>\d{8}T\d{6}Z)(?:-delta-(?P<device>[0-9a-f]+)-(?P<seq>\d+))?\.tar\.(?:bz2|gz|zst)$",
+        )
+        .unwrap();
+        let caps = regex.captures(file_name)?;
+        let data_time = Utc
+            .datetime_from_str(caps.name("time")?.as_str(), SNAPSHOT_TIME_FORMAT)
+            .ok()?;
+        let delta = match (caps.name("device"), caps.name("seq")) {
+            (Some(device), Some(seq)) => Some(DeltaInfo {
+                device_id: device.as_str().to_string(),
+                seq: seq.as_str().parse().ok()?,
+            }),
+            _ => None,
+        };
+
+        Some((data_time, delta))
+    }
+
+    /// Tar and compress the DB bytes, then encrypt them if a passphrase is given.
+    pub fn to_payload(&self, format: PayloadFormat, passphrase: Option<&str>) -> Result<Vec<u8>> {
+        // tar the db file and get bytes
+        let mut tar_bytes = Vec::new();
+        {
+            let mut tar = tar::Builder::new(&mut tar_bytes);
+            let mut db_file = tempfile::tempfile()?;
+            db_file.write_all(&self.db_bytes)?;
+            db_file.seek(SeekFrom::Start(0))?;
+            tar.append_file(Db::get_default_db_name(), &mut db_file)?;
+            tar.finish()?;
         }
 
-        imap_session.select("INBOX")?;
-
-        let mut message = None;
-        let mut fetches;
-        if !sync_config.workaround_imap_search.unwrap() {
-            let mut seq_list: Vec<_> = imap_session
-                .search("SUBJECT [wordmem][sync]")?
-                .into_iter()
-                .collect();
-            if !seq_list.is_empty() {
-                return Ok(None);
+        // compress the tar bytes
+        let mut compressed_bytes = Vec::new();
+        match format.compression {
+            PayloadCompression::Bzip2 => {
+                let level = match format.level {
+                    Some(level) => bzip2::Compression::new(level.clamp(1, 9) as u32),
+                    None => bzip2::Compression::default(),
+                };
+                let mut compressor = BzEncoder::new(&mut compressed_bytes, level);
+                compressor.write_all(&tar_bytes)?;
+                compressor.finish()?;
             }
-
-            seq_list.sort_unstable();
-            let last_seq = seq_list.last().unwrap();
-            fetches = imap_session.fetch(last_seq.to_string(), "RFC822")?;
-            message = Some(fetches.iter().next().unwrap());
-        } else {
-            let seq_list = imap_session.search("ALL")?;
-            if seq_list.is_empty() {
-                return Ok(None);
+            PayloadCompression::Gzip => {
+                let level = match format.level {
+                    Some(level) => flate2::Compression::new(level.clamp(0, 9) as u32),
+                    None => flate2::Compression::default(),
+                };
+                let mut compressor = GzEncoder::new(&mut compressed_bytes, level);
+                compressor.write_all(&tar_bytes)?;
+                compressor.finish()?;
             }
-
-            let mut seq_list: Vec<_> = seq_list.iter().collect();
-            seq_list.sort();
-            seq_list.reverse();
-            for chunk in seq_list.chunks(50) {
-                let seqset = chunk
-                    .iter()
-                    .map(|s| s.to_string())
-                    .collect::<Vec<_>>()
-                    .join(",");
-                fetches = imap_session.fetch(seqset, "BODY.PEEK[]")?;
-
-                let mut fetches_sorted = fetches.iter().collect::<Vec<_>>();
-                fetches_sorted.sort_by_key(|m| {
-                    mail_parser::Message::parse(m.body().unwrap())
-                        .unwrap()
-                        .get_date()
-                        .unwrap()
-                        .to_timestamp()
-                });
-                fetches_sorted.reverse();
-
-                message = fetches_sorted.into_iter().find(|m| {
-                    mail_parser::Message::parse(m.body().unwrap())
-                        .unwrap()
-                        .get_subject()
-                        .unwrap()
-                        .starts_with("[wordmem][sync]")
-                });
-                if message.is_some() {
-                    break;
-                }
+            PayloadCompression::Zstd => {
+                // level 0 is the default of zstd
+                let level = format.level.map_or(0, |l| l.clamp(1, 22));
+                compressed_bytes = zstd::encode_all(tar_bytes.as_slice(), level)?;
             }
         }
-        if message.is_none() {
-            return Ok(None);
+
+        match passphrase {
+            Some(passphrase) => encrypt_payload(&compressed_bytes, passphrase),
+            None => Ok(compressed_bytes),
         }
+    }
 
-        let message = mail_parser::Message::parse(message.unwrap().body().unwrap()).unwrap();
-        let subject = message.get_subject().unwrap();
-        let regex = Regex::new(r"\[(?P<info>[^\]]*)\]")?;
-        let last_cap = regex.captures_iter(subject).last().unwrap();
-        let time_str = last_cap.name("info").unwrap().as_str();
-        let data_time = DateTime::parse_from_rfc3339(time_str)?.with_timezone(&Utc);
-        let bzip_bytes = message
-            .get_attachment(0)
-            .unwrap()
-            .unwrap_binary()
-            .get_contents();
-
-        // extract db bytes
+    /// Extract the DB bytes from a payload made by `to_payload`, in any compression.
+    /// With a passphrase given, payloads not encrypted are refused unless `allow_plaintext`,
+    /// to merge snapshots pushed before encryption was enabled.
+    pub fn from_payload(
+        data_time: DateTime<Utc>,
+        payload: &[u8],
+        passphrase: Option<&str>,
+        allow_plaintext: bool,
+    ) -> Result<SyncData> {
+        let decrypted_bytes;
+        let compressed_bytes = if is_encrypted_payload(payload) {
+            let passphrase = passphrase.ok_or_else(|| {
+                Error::msg("Sync data is encrypted. Run `wordmem signin --encrypt` to set the sync passphrase.")
+            })?;
+            decrypted_bytes = decrypt_payload(payload, passphrase)?;
+            decrypted_bytes.as_slice()
+        } else if passphrase.is_some() && !allow_plaintext {
+            return Err(SyncError::UnencryptedPayload.into());
+        } else {
+            payload
+        };
+
+        let invalid = |e: std::io::Error| SyncError::invalid_payload(&e.to_string());
         let mut db_bytes = Vec::new();
         {
-            // decompress bzip bytes
-            let mut tar_bytes = Vec::new();
-            let mut decompressor = BzDecoder::new(bzip_bytes);
-            decompressor.read_to_end(&mut tar_bytes)?;
+            // decompress the tar bytes
+            let tar_bytes = match PayloadCompression::detect(compressed_bytes) {
+                Some(PayloadCompression::Bzip2) => {
+                    read_decompressed(BzDecoder::new(compressed_bytes), MAX_DECOMPRESSED_LEN)?
+                }
+                Some(PayloadCompression::Gzip) => {
+                    read_decompressed(GzDecoder::new(compressed_bytes), MAX_DECOMPRESSED_LEN)?
+                }
+                Some(PayloadCompression::Zstd) => read_decompressed(
+                    zstd::stream::read::Decoder::new(compressed_bytes).map_err(invalid)?,
+                    MAX_DECOMPRESSED_LEN,
+                )?,
+                None => return Err(SyncError::invalid_payload("unknown compression").into()),
+            };
 
             // extract tar
             let mut tar = Archive::new(tar_bytes.as_slice());
-            let mut db_file = tar.entries()?.into_iter().next().unwrap()?;
-            db_file.read_to_end(&mut db_bytes)?;
+            let mut db_file = tar
+                .entries()
+                .map_err(invalid)?
+                .next()
+                .ok_or_else(|| SyncError::invalid_payload("empty archive"))?
+                .map_err(invalid)?;
+            let entry_name = db_file.path().map_err(invalid)?.to_path_buf();
+            if entry_name.to_str() != Some(Db::get_default_db_name().as_str()) {
+                return Err(SyncError::invalid_payload(&format!(
+                    "unexpected archive entry {}",
+                    entry_name.display()
+                ))
+                .into());
+            }
+            db_file.read_to_end(&mut db_bytes).map_err(invalid)?;
         }
 
-        Ok(Some(SyncData {
+        Ok(SyncData {
             data_time,
             db_bytes,
-        }))
+            delta: None,
+        })
     }
 
-    pub fn push_data(&self, sync_config: Option<&SyncConfig>) -> Result<()> {
-        if sync_config.is_none() {
-            return Err(Error::msg("Sync config missing."));
-        }
+    /// Get the schema version of the DB bytes, which must have been validated.
+    pub fn get_schema_version(&self) -> i64 {
+        let mut version_bytes = [0; 4];
+        version_bytes.copy_from_slice(&self.db_bytes[USER_VERSION_OFFSET..USER_VERSION_OFFSET + 4]);
+        u32::from_be_bytes(version_bytes) as i64
+    }
 
-        let sync_config = sync_config.unwrap();
-        let password = sync_config.get_password()?;
-        if password.is_none() {
-            return Err(Error::msg("Sync password missing."));
+    /// Check that the DB bytes are a wordmem DB readable by this wordmem,
+    /// before anything is merged from them.
+    pub fn validate(&self) -> Result<()> {
+        if self.db_bytes.len() < SQLITE_HEADER_LEN || !self.db_bytes.starts_with(SQLITE_HEADER) {
+            return Err(SyncError::invalid_payload("not an SQLite DB").into());
         }
 
-        // tar the db file and get bytes
-        let mut tar_bytes = Vec::new();
-        {
-            let mut tar = tar::Builder::new(&mut tar_bytes);
-            let mut db_file = tempfile::tempfile()?;
-            db_file.write_all(&self.db_bytes)?;
-            db_file.seek(SeekFrom::Start(0))?;
-            tar.append_file(Db::get_default_db_name(), &mut db_file)?;
-            tar.finish()?;
+        let version = self.get_schema_version();
+        let supported = Db::get_latest_schema_version();
+        if version > supported {
+            return Err(SyncError::UnsupportedSchema { version, supported }.into());
         }
 
-        // bzip the tar bytes
-        let mut bzip_bytes = Vec::new();
-        {
-            let mut compressor = BzEncoder::new(&mut bzip_bytes, Compression::default());
-            compressor.write_all(&tar_bytes)?;
-            compressor.finish()?;
-        }
+        Ok(())
+    }
+}
+
+/// Read all a decoder gives, refusing more than `max_len` bytes.
+fn read_decompressed(decoder: impl Read, max_len: u64) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    decoder
+        .take(max_len + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| SyncError::invalid_payload(&e.to_string()))?;
+    if bytes.len() as u64 > max_len {
+        return Err(SyncError::invalid_payload("too large when decompressed").into());
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod sync_data_tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_payload_validation() -> Result<()> {
+        let db_file = tempfile::NamedTempFile::new()?;
+        let sync_data = SyncData {
+            data_time: Utc.timestamp(Utc::now().timestamp(), 0),
+            db_bytes: Db::new(db_file.path())?.read_bytes()?,
+            delta: None,
+        };
+        for compression in [
+            PayloadCompression::Bzip2,
+            PayloadCompression::Gzip,
+            PayloadCompression::Zstd,
+        ] {
+            let format = PayloadFormat {
+                compression,
+                level: Some(5),
+            };
+            let payload = sync_data.to_payload(format, None)?;
+            let read_back = SyncData::from_payload(sync_data.data_time, &payload, None, false)?;
+            assert_eq!(read_back.db_bytes, sync_data.db_bytes);
 
-        let message = lettre::Message::builder()
-            .from(sync_config.email.parse().unwrap())
-            .to(sync_config.email.parse().unwrap())
-            .subject(format!(
-                "[wordmem][sync][{}]",
-                self.data_time.to_rfc3339_opts(SecondsFormat::Secs, true)
-            ))
-            .singlepart(
-                Attachment::new(format!("{}.tar.bz2", Db::get_default_db_name())).body(
-                    bzip_bytes,
-                    ContentType::parse("application/octet-stream").unwrap(),
-                ),
-            )?;
-        let mailer = match sync_config.smtp_encryption {
-            Encryption::SslTls => SmtpTransport::relay(&sync_config.smtp_server_host)?,
-            Encryption::StartTls => SmtpTransport::starttls_relay(&sync_config.smtp_server_host)?,
+            let file_name = sync_data.get_snapshot_file_name(format);
+            assert!(SyncData::parse_snapshot_file_name(&file_name).is_some());
         }
-        .credentials(Credentials::new(
-            sync_config.email.clone(),
-            password.unwrap(),
-        ))
-        .port(sync_config.smtp_server_port)
-        .build();
-        mailer.send(&message)?;
+        let payload = sync_data.to_payload(PayloadFormat::default(), None)?;
+        let read_back = SyncData::from_payload(sync_data.data_time, &payload, None, false)?;
+        assert!(read_back.validate().is_ok());
+
+        // plaintext is refused once a passphrase is set, unless allowed for migration
+        let plaintext =
+            SyncData::from_payload(sync_data.data_time, &payload, Some("passphrase"), false);
+        assert!(matches!(
+            plaintext.unwrap_err().downcast_ref::<SyncError>(),
+            Some(SyncError::UnencryptedPayload)
+        ));
+        SyncData::from_payload(sync_data.data_time, &payload, Some("passphrase"), true)?;
+        let encrypted = sync_data.to_payload(PayloadFormat::default(), Some("passphrase"))?;
+        SyncData::from_payload(sync_data.data_time, &encrypted, Some("passphrase"), false)?;
+
+        let garbage = SyncData::from_payload(sync_data.data_time, b"garbage", None, false);
+        assert!(garbage.unwrap_err().downcast_ref::<SyncError>().is_some());
+
+        // DBs from a newer wordmem are refused
+        let mut db_bytes = read_back.db_bytes;
+        db_bytes[USER_VERSION_OFFSET + 3] += 1;
+        let newer = SyncData {
+            db_bytes,
+            ..sync_data
+        };
+        assert!(matches!(
+            newer.validate().unwrap_err().downcast_ref::<SyncError>(),
+            Some(SyncError::UnsupportedSchema { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompressed_limit() -> Result<()> {
+        let mut compressed_bytes = Vec::new();
+        let mut compressor = GzEncoder::new(&mut compressed_bytes, flate2::Compression::default());
+        compressor.write_all(&[0; 1000])?;
+        compressor.finish()?;
+
+        let bytes = read_decompressed(GzDecoder::new(compressed_bytes.as_slice()), 1000)?;
+        assert_eq!(bytes.len(), 1000);
+        let error =
+            read_decompressed(GzDecoder::new(compressed_bytes.as_slice()), 999).unwrap_err();
+        assert!(error.downcast_ref::<SyncError>().is_some());
 
         Ok(())
     }
@@ -0,0 +1,53 @@
+use std::fmt::{Display, Formatter};
+
+/// Problems with data found at a sync backend, as opposed to failures to reach it.
+/// Snapshots with such problems are skipped and reported rather than aborting syncing.
+#[derive(Debug)]
+pub enum SyncError {
+    /// a mail that looks like a sync mail, but cannot be understood
+    MalformedMail { id: String, reason: String },
+    /// a payload that does not hold a wordmem DB
+    InvalidPayload { reason: String },
+    /// a DB from a newer wordmem, which this one cannot read
+    UnsupportedSchema { version: i64, supported: i64 },
+    /// a payload not encrypted though a sync passphrase is set, which anyone able to write to
+    /// the backend could have put there
+    UnencryptedPayload,
+}
+
+impl SyncError {
+    pub fn malformed_mail(id: &str, reason: &str) -> SyncError {
+        SyncError::MalformedMail {
+            id: id.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn invalid_payload(reason: &str) -> SyncError {
+        SyncError::InvalidPayload {
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl Display for SyncError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncError::MalformedMail { id, reason } => {
+                write!(f, "Malformed sync mail {}: {}", id, reason)
+            }
+            SyncError::InvalidPayload { reason } => write!(f, "Invalid sync payload: {}", reason),
+            SyncError::UnsupportedSchema { version, supported } => write!(
+                f,
+                "Sync data has schema version {}, newer than {} supported. Please upgrade wordmem.",
+                version, supported
+            ),
+            SyncError::UnencryptedPayload => write!(
+                f,
+                "Sync data is not encrypted, though a sync passphrase is set. If it was pushed before encryption was enabled, run `wordmem pull --allow-plaintext` once to merge it."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
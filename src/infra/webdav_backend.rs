@@ -0,0 +1,277 @@
+use std::io::Read;
+
+use anyhow::{Error, Result};
+use regex::Regex;
+
+use crate::infra::{PayloadFormat, SnapshotInfo, SyncBackend, SyncData};
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:getcontentlength/></d:prop></d:propfind>"#;
+
+/// Sync backend keeping snapshots as files in a WebDAV collection, e.g. on Nextcloud.
+pub struct WebDavBackend {
+    /// URL of the collection, ending with `/`
+    base_url: String,
+    auth_header: Option<String>,
+    /// passphrase to encrypt payloads with, if any
+    passphrase: Option<String>,
+    /// whether payloads not encrypted are read though a passphrase is given
+    allow_plaintext: bool,
+    format: PayloadFormat,
+}
+
+impl WebDavBackend {
+    pub fn new(
+        url: &str,
+        username: &str,
+        password: Option<String>,
+        passphrase: Option<String>,
+    ) -> WebDavBackend {
+        let mut base_url = url.trim().to_string();
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+
+        WebDavBackend {
+            base_url,
+            auth_header: password
+                .map(|p| format!("Basic {}", base64::encode(format!("{}:{}", username, p)))),
+            passphrase,
+            allow_plaintext: false,
+            format: PayloadFormat::default(),
+        }
+    }
+
+    fn request(&self, method: &str, file_name: &str) -> ureq::Request {
+        let request = ureq::request(method, &format!("{}{}", self.base_url, file_name));
+        match &self.auth_header {
+            Some(auth_header) => request.set("Authorization", auth_header),
+            None => request,
+        }
+    }
+
+    fn parse_multistatus(xml: &str) -> Result<Vec<SnapshotInfo>> {
+        let response_regex =
+            Regex::new(r"(?s)<(?:\w+:)?response\b[^>]*>(?P<body>.*?)</(?:\w+:)?response>")?;
+        let href_regex = Regex::new(r"<(?:\w+:)?href\b[^>]*>(?P<href>[^<]*)</")?;
+        let length_regex =
+            Regex::new(r"<(?:\w+:)?getcontentlength\b[^>]*>\s*(?P<length>\d+)\s*</")?;
+
+        let mut snapshots = Vec::new();
+        for response in response_regex.captures_iter(xml) {
+            let body = response.name("body").unwrap().as_str();
+            let href = match href_regex.captures(body) {
+                Some(caps) => caps.name("href").unwrap().as_str().to_string(),
+                None => continue,
+            };
+            let file_name = href.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+            let (data_time, delta) = match SyncData::parse_snapshot_file_name(file_name) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            snapshots.push(SnapshotInfo {
+                id: file_name.to_string(),
+                data_time,
+                size: length_regex
+                    .captures(body)
+                    .and_then(|caps| caps.name("length").unwrap().as_str().parse().ok()),
+                delta,
+            });
+        }
+        snapshots.sort_by(|a, b| b.data_time.cmp(&a.data_time));
+
+        Ok(snapshots)
+    }
+}
+
+impl SyncBackend for WebDavBackend {
+    fn list_snapshots(&mut self) -> Result<Vec<SnapshotInfo>> {
+        let response = self
+            .request("PROPFIND", "")
+            .set("Depth", "1")
+            .set("Content-Type", "application/xml; charset=utf-8")
+            .send_string(PROPFIND_BODY);
+        let xml = match response {
+            Ok(response) => response.into_string()?,
+            Err(ureq::Error::Status(404, _)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        WebDavBackend::parse_multistatus(&xml)
+    }
+
+    fn fetch(&mut self, snapshot: &SnapshotInfo) -> Result<SyncData> {
+        let mut payload = Vec::new();
+        self.request("GET", &snapshot.id)
+            .call()?
+            .into_reader()
+            .read_to_end(&mut payload)?;
+        let mut sync_data = SyncData::from_payload(
+            snapshot.data_time,
+            &payload,
+            self.passphrase.as_deref(),
+            self.allow_plaintext,
+        )?;
+        sync_data.delta = snapshot.delta.clone();
+        Ok(sync_data)
+    }
+
+    fn upload(&mut self, data: &SyncData) -> Result<()> {
+        let id = data.get_snapshot_file_name(self.format);
+        let payload = data.to_payload(self.format, self.passphrase.as_deref())?;
+        match self.request("PUT", &id).send_bytes(&payload) {
+            Ok(_) => {}
+            // the collection does not exist yet
+            Err(ureq::Error::Status(404 | 409, _)) => {
+                self.request("MKCOL", "").call()?;
+                self.request("PUT", &id).send_bytes(&payload)?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(())
+    }
+
+    fn set_payload_format(&mut self, format: PayloadFormat) {
+        self.format = format;
+    }
+
+    fn set_allow_plaintext(&mut self, allow: bool) {
+        self.allow_plaintext = allow;
+    }
+
+    fn delete_snapshots(&mut self, snapshots: &[&SnapshotInfo]) -> Result<()> {
+        for s in snapshots.iter() {
+            self.request("DELETE", &s.id)
+                .call()
+                .map_err(|e| Error::msg(format!("Failed to delete snapshot {}: {}", s.id, e)))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod webdav_backend_tests {
+    use std::{
+        collections::BTreeMap,
+        io::{BufRead, BufReader, Write},
+        net::TcpStream,
+        sync::{Arc, Mutex},
+    };
+
+    use anyhow::Result;
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::*;
+    use crate::infra::serve_local;
+
+    type Files = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+    /// A minimal WebDAV stand-in serving one collection from memory, one request per connection.
+    fn start_webdav_server() -> Result<(String, Files)> {
+        let files: Files = Arc::new(Mutex::new(BTreeMap::new()));
+        let server_files = files.clone();
+        let port = serve_local(move |stream| handle_webdav_request(stream, &server_files))?;
+
+        Ok((format!("http://127.0.0.1:{}/dav/", port), files))
+    }
+
+    fn handle_webdav_request(stream: TcpStream, files: &Files) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse()?;
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let name = path.trim_start_matches("/dav/").to_string();
+        let mut files = files.lock().unwrap();
+        let (status, response_body) = match method.as_str() {
+            "PROPFIND" => {
+                let mut xml =
+                    String::from(r#"<?xml version="1.0"?><d:multistatus xmlns:d="DAV:">"#);
+                xml += "<d:response><d:href>/dav/</d:href></d:response>";
+                for (name, bytes) in files.iter() {
+                    xml += &format!(
+                        "<d:response><d:href>/dav/{}</d:href><d:propstat><d:prop>\
+                        <d:getcontentlength>{}</d:getcontentlength></d:prop></d:propstat></d:response>",
+                        name,
+                        bytes.len()
+                    );
+                }
+                xml += "</d:multistatus>";
+                ("207 Multi-Status", xml.into_bytes())
+            }
+            "PUT" => {
+                files.insert(name, body);
+                ("201 Created", Vec::new())
+            }
+            "GET" => match files.get(&name) {
+                Some(bytes) => ("200 OK", bytes.clone()),
+                None => ("404 Not Found", Vec::new()),
+            },
+            "DELETE" => match files.remove(&name) {
+                Some(_) => ("204 No Content", Vec::new()),
+                None => ("404 Not Found", Vec::new()),
+            },
+            _ => ("405 Method Not Allowed", Vec::new()),
+        };
+
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            response_body.len()
+        )?;
+        stream.write_all(&response_body)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_webdav_backend() -> Result<()> {
+        let (url, files) = start_webdav_server()?;
+        let mut backend = WebDavBackend::new(&url, "user", Some("password".to_string()), None);
+        assert!(backend.fetch_latest()?.is_none());
+
+        let now = Utc.timestamp(Utc::now().timestamp(), 0);
+        for (i, bytes) in [b"older", b"newer"].iter().enumerate() {
+            backend.upload(&SyncData {
+                data_time: now + Duration::seconds(i as i64),
+                db_bytes: bytes.to_vec(),
+                delta: None,
+            })?;
+        }
+        assert_eq!(files.lock().unwrap().len(), 2);
+
+        let snapshots = backend.list_snapshots()?;
+        assert_eq!(snapshots.len(), 2);
+        assert!(snapshots[0].size.is_some());
+        let latest = backend.fetch_latest()?.unwrap();
+        assert_eq!(latest.data_time, now + Duration::seconds(1));
+        assert_eq!(latest.db_bytes, b"newer".to_vec());
+
+        assert_eq!(backend.prune(1)?, 1);
+        assert_eq!(files.lock().unwrap().len(), 1);
+
+        Ok(())
+    }
+}
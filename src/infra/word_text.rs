@@ -0,0 +1,150 @@
+use anyhow::{Error, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::infra::Word;
+
+const WORD_TEXT_HEADER: &str = "# wordmem words v1: name\tmeanings\tperiod_days\tlast_visit\tnext_visit\tupdated_at\tdevice_id\tdeleted_at\texamples\ttags";
+
+/// Write words in a deterministic, diff-friendly text format: one word per line sorted by name,
+/// with tab-separated fields and sorted meanings and tags.
+pub fn words_to_text(words: &[Word]) -> String {
+    let mut words: Vec<_> = words.iter().collect();
+    words.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut text = String::from(WORD_TEXT_HEADER);
+    text.push('\n');
+    for w in words {
+        let fields = [
+            w.name.clone(),
+            sort_list(&w.meanings),
+            w.period_days.to_string(),
+            format_time(&w.last_visit),
+            format_time(&w.next_visit),
+            format_time(&w.updated_at),
+            w.device_id.clone(),
+            w.deleted_at.as_ref().map(format_time).unwrap_or_default(),
+            w.examples.clone(),
+            sort_list(&w.tags),
+        ];
+        let line = fields
+            .iter()
+            .map(|f| escape_field(f))
+            .collect::<Vec<_>>()
+            .join("\t");
+        text += &line;
+        text.push('\n');
+    }
+
+    text
+}
+
+/// Read words written by `words_to_text`.
+pub fn text_to_words(text: &str) -> Result<Vec<Word>> {
+    let mut words = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<_> = line.split('\t').map(unescape_field).collect();
+        if fields.len() != 10 {
+            return Err(Error::msg(format!(
+                "Line {}: expected 10 fields, found {}.",
+                i + 1,
+                fields.len()
+            )));
+        }
+
+        words.push(Word {
+            id: None,
+            name: fields[0].clone(),
+            meanings: fields[1].clone(),
+            period_days: fields[2].parse()?,
+            last_visit: parse_time(&fields[3])?,
+            next_visit: parse_time(&fields[4])?,
+            updated_at: parse_time(&fields[5])?,
+            device_id: fields[6].clone(),
+            deleted_at: if fields[7].is_empty() {
+                None
+            } else {
+                Some(parse_time(&fields[7])?)
+            },
+            examples: fields[8].clone(),
+            tags: fields[9].clone(),
+        });
+    }
+
+    Ok(words)
+}
+
+fn sort_list(list: &str) -> String {
+    let mut items: Vec<_> = list.split(';').filter(|i| !i.is_empty()).collect();
+    items.sort_unstable();
+    items.join(";")
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn parse_time(s: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(s)?.with_timezone(&Utc))
+}
+
+fn escape_field(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape_field(s: &str) -> String {
+    let mut result = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some(c) => result.push(c),
+            None => result.push('\\'),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod word_text_tests {
+    use anyhow::Result;
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_word_text_round_trip() -> Result<()> {
+        let mut word = Word::from_name_and_meanings("tab", "a\tkey;b");
+        word.last_visit = Utc.timestamp(1_600_000_000, 0);
+        word.next_visit = Utc.timestamp(1_600_086_400, 0);
+        word.updated_at = Utc.timestamp(1_600_000_000, 0);
+        word.deleted_at = Some(Utc.timestamp(1_600_000_001, 0));
+        word.device_id = "device".to_string();
+        word.examples = "line 1\nline \\2".to_string();
+        word.tags = "z;a".to_string();
+
+        let text = words_to_text(&[word]);
+        assert_eq!(text.lines().count(), 2);
+        let words = text_to_words(&text)?;
+        assert_eq!(words.len(), 1);
+        assert_eq!(words[0].meanings, "a\tkey;b");
+        assert_eq!(words[0].examples, "line 1\nline \\2");
+        assert_eq!(words[0].tags, "a;z");
+        assert_eq!(words[0].deleted_at, Some(Utc.timestamp(1_600_000_001, 0)));
+        assert_eq!(words_to_text(&words), text);
+
+        Ok(())
+    }
+}
@@ -7,7 +7,7 @@
 //! - `word-manager`, which takes and manages words from user
 //! - `word-visitor`, which makes user revisit the words periodically
 //! - `revisit-planner`, which plans the revisiting schedule
-//! - `db-syncer`, which syncs data from/to email
+//! - `db-syncer`, which syncs data from/to email or other sync backends
 //!
 //! Revisiting means test. User need to spell out the word and the explanation respectively in 2 passes.
 //!
@@ -22,27 +22,53 @@
 //! ## DESIGN
 //!
 //! Features:
-//! - Storage can be synced via email.
-//! - Security keys should be stored in system keyring.
+//! - Storage can be synced via email, WebDAV, a git repository, or a directory.
+//! - Syncing merges words per word by modification time, so changes on different devices are kept.
+//! - Pushes upload only the words changed since the last push, as delta snapshots after a full one. Once `compact_after_deltas` (20 by default) deltas pile up, a full snapshot is pushed instead. Pulls fetch only snapshots not merged before.
+//! - With `auto_sync` enabled in config, `take`/`test`/`change`/`delete` pull first, and changes are pushed right after. When remote is unreachable within `auto_sync_timeout_secs` (15 by default), the command goes on offline and the push is retried by the next command. A push timed out keeps going in the background, and is waited for as long again before the command exits.
+//! - Words are due by study day rather than by hour. A study day starts at `day_rollover_hour` (4 AM by default) in `timezone` (an IANA name such as `Asia/Shanghai`, the system local time by default), and revisits are scheduled to the start of the target study day.
+//! - Deleted words are kept as tombstones until `wordmem gc`, so that deletions are synced as well.
+//! - Sync payloads are compressed by bzip2, or by gzip or zstd with `sync_compression` set to `Gzip` or `Zstd` in config, at `sync_compression_level` if set. The compression is carried in the file or attachment name, and payloads in any of them are read.
+//! - Sync data can be encrypted end-to-end with a passphrase (Argon2id + XChaCha20-Poly1305).
+//! - Mail servers with their own CA can be trusted by `tls.ca_file`, a PEM bundle, in the email config or signin file, and their certificates pinned by `tls.pinned_sha256`, a list of SHA-256 fingerprints. Pins are only checked for IMAP, as the SMTP transport gives no access to its certificate, so SMTP is not used with them and sync mails are put by IMAP into a sync mailbox other than INBOX. `tls.insecure_skip_verify` turns off certificate checks for test servers, and encryption `none` connects in plaintext to stand-in servers on localhost only, and is refused for other hosts.
+//! - IMAP ID (RFC 2971) identifying wordmem by name and version is sent after login when the server announces it, as some providers such as 163.com require. Set `imap_id` in the email config to `true` or `false` to always or never send it.
+//! - Security keys should be stored in system keyring. Where no keyring service is available, e.g. on headless servers and containers, they are kept in a file encrypted with a password instead, which can be given by `WORDMEM_SECRETS_PASSWORD`. Set `secret_store` to `Keyring` or `File` in config to choose one.
 //! - Words can be exported to/imported from file.
 //!
 //! Commandline interface:
 //! - `wordmem take`: take words from user.
+//! - `wordmem take --file <file>`: take a list of words from a file. Piped stdin is read the same way.
 //! - `wordmem test`: do tests.
-//! - `wordmem signin`: sign in email to enable syncing.
+//! - `wordmem signin`: sign in email to enable syncing. Sync mails are kept in a dedicated mailbox, `wordmem` by default, created if missing. A test mail is sent and waited for up to `--test-timeout` seconds (60 by default) before being deleted. If the test fails, the step failed, e.g. SMTP auth or IMAP login, is reported.
+//! - `wordmem signin --provider gmail|outlook|fastmail|icloud --email <email>`: sign in without being asked for mail server settings. Each setting can also be given by a flag such as `--imap-host`/`--smtp-port`/`--imap-encryption`, an environment variable such as `WORDMEM_IMAP_HOST`, or a JSON file by `--signin-file` with the field names of the config file. Only the settings missing are asked for.
+//! - `wordmem signin --proxy socks5://[user:password@]host:port` or `http://...`: connect to IMAP and SMTP servers through a SOCKS5 or HTTP CONNECT proxy, tunnelled before TLS. The username and password of the proxy are kept in the secret store, not in the config file. Without `proxy` in the email config, `ALL_PROXY` is used if set, except for servers in `NO_PROXY`, and skipped with a warning if it is not a proxy wordmem can use. SMTP goes through a local port forwarding to the proxy, which is closed as soon as the SMTP client has connected.
+//! - `wordmem signin --password-stdin` or `--password-command <command>`: read the password from stdin, or from the output of a command such as a password manager, so that machines can be provisioned by scripts.
+//! - `wordmem signin --oauth2 google|microsoft --client-id <id> [--client-secret <secret>]`: sign in email by OAuth2 (XOAUTH2) instead of password. The refresh token and the client secret are kept in system keyring, and access tokens are refreshed before each sync.
+//! - `wordmem signin --dir <dir>`: sync to snapshots in a directory, e.g. one synced by Syncthing/Dropbox or on a USB stick.
+//! - `wordmem signin --webdav <url> --username <user>`: sync to snapshots in a WebDAV collection, e.g. on Nextcloud.
+//! - `wordmem signin --git <remote> --git-repo <dir> [--git-branch <branch>]`: sync to commits of a diff-friendly word list in a git repository.
+//! - `wordmem signin --encrypt [...]`: also encrypt sync data with a passphrase, kept in system keyring.
 //! - `wordmem signout`: sign out email to disable syncing.
-//! - `wordmem push`: forcibly push data to email to keep synced.
-//! - `wordmem pull`: forcibly pull data from email to keep synced.
+//! - `wordmem push`: forcibly push data to remote to keep synced.
+//! - `wordmem pull`: forcibly pull data from remote to keep synced.
+//! - `wordmem pull --allow-plaintext`: also merge snapshots pushed before encryption was enabled. Otherwise, with a sync passphrase set, snapshots not encrypted are skipped, since anyone able to write to the sync backend could have put them there.
+//! - `wordmem secrets migrate --to keyring|file`: move passwords, tokens and the sync passphrase between system keyring and the encrypted secret file.
+//! - `wordmem sync status`: show last push/pull times, local changes not pushed and remote snapshots not merged, and whether to push or pull.
+//! - `wordmem sync list`: list snapshots available at remote with their times, kinds and sizes.
+//! - `wordmem sync prune --keep N`: delete snapshots at remote older than the newest N full ones. After each push, the newest `keep_snapshots` (10 by default) full snapshots and the deltas after them are kept, and pruned sync mails are moved to `trash_mailbox` if configured.
 //! - `wordmem change <word>`: change meanings of an existing word.
 //! - `wordmem delete <word>`: delete a word.
 //! - `wordmem open <word>`: open a word on https://translate.bing.com.
 //! - `wordmem clear`: remove all words in DB.
+//! - `wordmem gc [--days N]`: purge tombstones of deleted words once all devices have synced.
 //! - `wordmem export <file>`: export words to a file.
+//! - `wordmem export --format md|html [--group-by tag|due] <file>`: export a printable study sheet.
 //! - `wordmem import <file>`: import words from a file.
+//! - `wordmem import --format kindle <vocab.db>`: import looked-up words from Kindle Vocabulary Builder.
 //!
 //! Implementation:
 //! - SQLite for storage of words.
-//! - JSON format for exported file of words.
+//! - JSON format for exported file of words, Markdown/HTML for study sheets.
 //! - Compressed .sqlite file as attachment and with INI format config info as body in email for syncing.
 
 mod db_syncer;
@@ -51,10 +77,14 @@ mod revisit_planner;
 mod word_manager;
 mod word_visitor;
 
+use std::time::Duration;
+
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, Parser, Subcommand};
 
-use crate::infra::{AppConfig, Db};
+use crate::infra::{
+    AppConfig, Db, DirConfig, Encryption, GitConfig, SecretStore, SyncConfig, WebDavConfig,
+};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -67,17 +97,133 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Take words from user
-    Take,
+    Take {
+        /// Read a list of words from a file instead of stdin
+        #[clap(long)]
+        file: Option<String>,
+    },
     /// Do tests
     Test,
     /// Sign in email to enable syncing
-    Signin,
+    #[clap(group(ArgGroup::new("backend").args(&["dir", "webdav", "git"])))]
+    #[clap(group(
+        ArgGroup::new("email-options")
+            .multiple(true)
+            .args(&[
+                "oauth2",
+                "provider",
+                "imap-host",
+                "imap-port",
+                "imap-encryption",
+                "smtp-host",
+                "smtp-port",
+                "smtp-encryption",
+                "email",
+                "sync-mailbox",
+                "proxy",
+                "signin-file",
+            ])
+            .conflicts_with_all(&["dir", "webdav", "git"])
+    ))]
+    Signin {
+        /// Sync to snapshots in a directory instead of email
+        #[clap(long)]
+        dir: Option<String>,
+        /// Sync to snapshots in a WebDAV collection instead of email
+        #[clap(long, value_name = "URL")]
+        webdav: Option<String>,
+        /// User name of the WebDAV server
+        #[clap(long, requires = "webdav")]
+        username: Option<String>,
+        /// Sync to commits in a git repository instead of email
+        #[clap(long, value_name = "REMOTE", requires = "git-repo")]
+        git: Option<String>,
+        /// Directory of the local clone of the git repository
+        #[clap(long, value_name = "DIR")]
+        git_repo: Option<String>,
+        /// Branch of the git repository to commit to [default: main]
+        #[clap(long, requires = "git")]
+        git_branch: Option<String>,
+        /// Log in to mail servers by OAuth2 of the provider instead of password
+        #[clap(long, arg_enum, requires = "client-id")]
+        oauth2: Option<db_syncer::OAuth2Provider>,
+        /// Client ID of the OAuth2 app registered with the provider
+        #[clap(long, requires = "oauth2")]
+        client_id: Option<String>,
+        /// Client secret of the OAuth2 app, if the provider requires one
+        #[clap(long, requires = "oauth2")]
+        client_secret: Option<String>,
+        /// Encrypt sync data with a passphrase
+        #[clap(long, conflicts_with = "git")]
+        encrypt: bool,
+        /// Fill in mail server settings of a common provider
+        #[clap(long, arg_enum)]
+        provider: Option<db_syncer::MailProvider>,
+        /// IMAP server host
+        #[clap(long, env = "WORDMEM_IMAP_HOST")]
+        imap_host: Option<String>,
+        /// IMAP server port
+        #[clap(long, env = "WORDMEM_IMAP_PORT")]
+        imap_port: Option<u16>,
+        /// IMAP server encryption: ssltls, starttls or none
+        #[clap(long, env = "WORDMEM_IMAP_ENCRYPTION")]
+        imap_encryption: Option<Encryption>,
+        /// SMTP server host
+        #[clap(long, env = "WORDMEM_SMTP_HOST")]
+        smtp_host: Option<String>,
+        /// SMTP server port
+        #[clap(long, env = "WORDMEM_SMTP_PORT")]
+        smtp_port: Option<u16>,
+        /// SMTP server encryption: ssltls, starttls or none
+        #[clap(long, env = "WORDMEM_SMTP_ENCRYPTION")]
+        smtp_encryption: Option<Encryption>,
+        /// Email address
+        #[clap(long, env = "WORDMEM_EMAIL")]
+        email: Option<String>,
+        /// Mailbox for sync mails [default: wordmem]
+        #[clap(long, env = "WORDMEM_SYNC_MAILBOX")]
+        sync_mailbox: Option<String>,
+        /// Proxy to connect to mail servers through, e.g. socks5://host:1080 or http://host:8080
+        #[clap(long, env = "WORDMEM_PROXY")]
+        proxy: Option<String>,
+        /// Read email settings missing from flags from a JSON file, with the field names of the config file
+        #[clap(long, value_name = "FILE", env = "WORDMEM_SIGNIN_FILE")]
+        signin_file: Option<String>,
+        /// Read the password from the first line of stdin, after any other values read from it
+        #[clap(long, conflicts_with = "password-command")]
+        password_stdin: bool,
+        /// Get the password from the first line of the output of a shell command
+        #[clap(long, value_name = "COMMAND", env = "WORDMEM_PASSWORD_COMMAND")]
+        password_command: Option<String>,
+        /// Seconds to wait for the test mail to arrive before giving up
+        #[clap(
+            long,
+            value_name = "SECS",
+            default_value = "60",
+            env = "WORDMEM_TEST_TIMEOUT"
+        )]
+        test_timeout: u64,
+    },
     /// Sign out to disable syncing
     Signout,
-    /// Forcibly push data to email to keep synced
+    /// Forcibly push data to remote to keep synced
     Push,
-    /// Forcibly pull data from email to keep synced
-    Pull,
+    /// Forcibly pull data from remote to keep synced
+    Pull {
+        /// Also merge snapshots not encrypted, pushed before encryption was enabled
+        #[clap(long)]
+        allow_plaintext: bool,
+    },
+    /// Manage snapshots at the sync backend
+    Sync {
+        #[clap(subcommand)]
+        command: SyncCommands,
+    },
+    /// Manage where secrets are kept
+    Secrets {
+        #[clap(subcommand)]
+        command: SecretsCommands,
+    },
     /// Change meanings of an existing word
     Change { word: String },
     /// Delete a word
@@ -86,79 +232,298 @@ enum Commands {
     Open { word: String },
     /// Remove all words in DB
     Clear,
+    /// Purge tombstones of deleted words once all devices have synced
+    Gc {
+        /// Purge tombstones older than this many days [default: from config, or 30]
+        #[clap(long)]
+        days: Option<u32>,
+    },
     /// Export words to a file
-    Export { file: String },
+    Export {
+        file: String,
+        /// Format of the file
+        #[clap(long, arg_enum, default_value = "json")]
+        format: word_manager::ExportFormat,
+        /// How to group words in a study sheet
+        #[clap(long, arg_enum, default_value = "tag")]
+        group_by: word_manager::GroupBy,
+    },
     /// Import words from a file
-    Import { file: String },
+    Import {
+        file: String,
+        /// Format of the file
+        #[clap(long, arg_enum, default_value = "json")]
+        format: word_manager::ImportFormat,
+    },
 }
 
-fn pull_data() -> Result<()> {
-    let default_conf_file = AppConfig::get_default_conf_path();
-    let app_config = AppConfig::load_from_file(&default_conf_file)?;
-    db_syncer::pull_data_from_email(app_config.as_ref())?;
+#[derive(Subcommand)]
+enum SyncCommands {
+    /// Show whether this device is ahead of, behind, or diverged from remote
+    Status,
+    /// List snapshots available at remote
+    List,
+    /// Delete old snapshots at remote
+    Prune {
+        /// Number of the newest full snapshots to keep, at least 1
+        #[clap(long, parse(try_from_str = parse_keep))]
+        keep: usize,
+    },
+}
 
-    Ok(())
+#[derive(Subcommand)]
+enum SecretsCommands {
+    /// Move passwords, tokens and the sync passphrase to another secret store
+    Migrate {
+        /// Store to move secrets to: keyring or file
+        #[clap(long)]
+        to: SecretStore,
+    },
 }
 
-fn push_data() -> Result<()> {
-    let default_conf_file = AppConfig::get_default_conf_path();
-    let app_config = AppConfig::load_from_file(&default_conf_file)?;
-    db_syncer::push_data_to_email(app_config.as_ref())?;
+/// Parse the number of snapshots to keep, refusing 0, which would delete all of them.
+fn parse_keep(s: &str) -> std::result::Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(keep) if keep >= 1 => Ok(keep),
+        _ => Err("must be a whole number of at least 1".to_string()),
+    }
+}
 
-    Ok(())
+/// Run a command on local DB, syncing around it if auto sync is enabled.
+fn run_with_auto_sync<T, F>(
+    app_config: Option<&AppConfig>,
+    pull_first: bool,
+    command: F,
+) -> Result<T>
+where
+    F: FnOnce(&Db) -> Result<T>,
+{
+    let db = Db::new(Db::get_default_db_path())?;
+    if !db_syncer::is_auto_sync_enabled(app_config) {
+        return command(&db);
+    }
+
+    let app_config = app_config.unwrap();
+    let mut jobs = db_syncer::AutoSyncJobs::default();
+    db_syncer::auto_sync_before(&db, app_config, pull_first, &mut jobs)?;
+    let change_marker = db.get_change_marker()?;
+    let result = command(&db);
+    if result.is_ok() && db.get_change_marker()? != change_marker {
+        db_syncer::auto_push(&db, app_config, &mut jobs)?;
+    }
+    jobs.finish(app_config);
+
+    result
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
     let default_db_file = Db::get_default_db_path();
     let default_conf_file = AppConfig::get_default_conf_path();
+    let app_config = AppConfig::load_from_file(&default_conf_file)?;
+    let calendar = revisit_planner::StudyCalendar::from_config(app_config.as_ref())?;
     match &cli.command {
-        Commands::Take => {
-            word_manager::read_words_to_db(&Db::new(default_db_file)?)?;
+        Commands::Take { file } => {
+            run_with_auto_sync(app_config.as_ref(), true, |db| match file {
+                Some(file) => word_manager::read_word_file_to_db(db, file, &calendar),
+                None => word_manager::read_words_to_db(db, &calendar),
+            })?;
         }
         Commands::Test => {
-            word_visitor::do_tests(&Db::new(default_db_file)?)?;
+            run_with_auto_sync(app_config.as_ref(), true, |db| {
+                word_visitor::do_tests(db, &calendar)
+            })?;
         }
-        Commands::Signin => {
-            let mut sync_config = db_syncer::read_sync_config()?;
-            if db_syncer::test_sync_config(&mut sync_config)? {
-                let app_config = AppConfig::load_from_file(&default_conf_file)?;
-                let mut app_config = app_config.unwrap_or(AppConfig { sync: None });
-                app_config.sync = Some(sync_config);
-                app_config.save_to_file(&default_conf_file)?;
+        Commands::Signin {
+            dir,
+            webdav,
+            username,
+            git,
+            git_repo,
+            git_branch,
+            oauth2,
+            client_id,
+            client_secret,
+            encrypt,
+            provider,
+            imap_host,
+            imap_port,
+            imap_encryption,
+            smtp_host,
+            smtp_port,
+            smtp_encryption,
+            email,
+            sync_mailbox,
+            proxy,
+            signin_file,
+            password_stdin,
+            password_command,
+            test_timeout,
+        } => {
+            let mut app_config = app_config.unwrap_or_default();
+            let secrets = app_config.secrets.clone();
+            if *encrypt && !db_syncer::read_sync_passphrase(&app_config)? {
+                return Ok(());
+            }
+
+            let password_source = match password_command {
+                _ if *password_stdin => db_syncer::PasswordSource::Stdin,
+                Some(command) => db_syncer::PasswordSource::Command(command.clone()),
+                None => db_syncer::PasswordSource::Prompt,
+            };
+
+            let sync_config = if let Some(dir) = dir {
+                Some(SyncConfig::Directory(DirConfig { dir: dir.into() }))
+            } else if let Some(git_remote) = git {
+                Some(SyncConfig::Git(GitConfig {
+                    git_remote: git_remote.clone(),
+                    git_repo: git_repo.clone().unwrap_or_default().into(),
+                    git_branch: git_branch.clone(),
+                }))
+            } else if let Some(webdav_url) = webdav {
+                let webdav_config = WebDavConfig {
+                    webdav_url: webdav_url.clone(),
+                    webdav_username: username.clone().unwrap_or_default(),
+                };
+                db_syncer::read_webdav_password(&webdav_config, &password_source, &secrets)?;
+                if db_syncer::test_webdav_config(&webdav_config, &secrets)? {
+                    Some(SyncConfig::WebDav(webdav_config))
+                } else {
+                    SyncConfig::WebDav(webdav_config).clear_secrets(&secrets)?;
+                    None
+                }
             } else {
-                sync_config.clear_password()?;
+                let oauth2_config = oauth2.map(|p| {
+                    p.get_oauth2_config(
+                        client_id.as_deref().unwrap_or_default(),
+                        client_secret.as_deref(),
+                    )
+                });
+                // flags and environment variables take precedence over the file and the preset
+                let mut options = db_syncer::EmailSigninOptions {
+                    imap_server_host: imap_host.clone(),
+                    imap_server_port: *imap_port,
+                    imap_encryption: imap_encryption.clone(),
+                    smtp_server_host: smtp_host.clone(),
+                    smtp_server_port: *smtp_port,
+                    smtp_encryption: smtp_encryption.clone(),
+                    email: email.clone(),
+                    sync_mailbox: sync_mailbox.clone(),
+                    proxy: proxy.clone(),
+                    tls: None,
+                };
+                if let Some(signin_file) = signin_file {
+                    options =
+                        options.or(db_syncer::EmailSigninOptions::load_from_file(signin_file)?);
+                }
+                if let Some(provider) = provider {
+                    options = options.or(provider.get_signin_options());
+                }
+                let mut email_config = db_syncer::read_email_config(
+                    options,
+                    oauth2_config,
+                    &password_source,
+                    &secrets,
+                )?;
+                if db_syncer::test_email_config(
+                    &mut email_config,
+                    Duration::from_secs(*test_timeout),
+                    &secrets,
+                )? {
+                    Some(SyncConfig::Email(email_config))
+                } else {
+                    SyncConfig::Email(email_config).clear_secrets(&secrets)?;
+                    None
+                }
+            };
+            if sync_config.is_some() {
+                app_config.sync = sync_config;
+                app_config.encrypt_sync = Some(*encrypt);
+                app_config.save_to_file(&default_conf_file)?;
+            } else if *encrypt {
+                app_config.clear_sync_passphrase()?;
             }
         }
         Commands::Signout => {
-            let app_config = AppConfig::load_from_file(&default_conf_file)?;
             if let Some(mut app_config) = app_config {
-                if let Some(sync_config) = app_config.sync {
-                    sync_config.clear_password()?;
+                if let Some(sync_config) = &app_config.sync {
+                    sync_config.clear_secrets(&app_config.secrets)?;
                 }
+                app_config.clear_sync_passphrase()?;
                 app_config.sync = None;
+                app_config.encrypt_sync = None;
                 app_config.save_to_file(&default_conf_file)?;
             }
         }
-        Commands::Push => push_data()?,
-        Commands::Pull => pull_data()?,
+        Commands::Push => {
+            db_syncer::push_data_to_remote(app_config.as_ref())?;
+        }
+        Commands::Pull { allow_plaintext } => {
+            db_syncer::pull_data_from_remote(app_config.as_ref(), *allow_plaintext)?;
+        }
+        Commands::Sync { command } => match command {
+            SyncCommands::Status => {
+                db_syncer::print_sync_status(app_config.as_ref())?;
+            }
+            SyncCommands::List => {
+                db_syncer::list_remote_snapshots(app_config.as_ref())?;
+            }
+            SyncCommands::Prune { keep } => {
+                db_syncer::prune_remote_snapshots(app_config.as_ref(), *keep)?;
+            }
+        },
+        Commands::Secrets { command } => match command {
+            SecretsCommands::Migrate { to } => {
+                let mut app_config = app_config.unwrap_or_default();
+                let moved = infra::migrate_secrets(
+                    &app_config.secrets,
+                    &app_config.get_secret_accounts(),
+                    *to,
+                )?;
+                app_config.secret_store = Some(*to);
+                app_config.save_to_file(&default_conf_file)?;
+                println!("Moved {} secrets to {}.", moved, to);
+            }
+        },
         Commands::Change { word } => {
-            word_manager::change_word(&Db::new(default_db_file)?, word)?;
+            run_with_auto_sync(app_config.as_ref(), true, |db| {
+                word_manager::change_word(db, word)
+            })?;
         }
         Commands::Delete { word } => {
-            word_manager::delete_word(&Db::new(default_db_file)?, word)?;
+            run_with_auto_sync(app_config.as_ref(), true, |db| {
+                word_manager::delete_word(db, word)
+            })?;
         }
         Commands::Open { word } => {
             word_manager::open_word(word)?;
         }
         Commands::Clear => {
-            word_manager::clear_words(&Db::new(default_db_file)?)?;
+            run_with_auto_sync(app_config.as_ref(), false, word_manager::clear_words)?;
+        }
+        Commands::Gc { days } => {
+            let days = days
+                .or_else(|| app_config.and_then(|c| c.tombstone_max_age_days))
+                .unwrap_or(AppConfig::DEFAULT_TOMBSTONE_MAX_AGE_DAYS);
+            word_manager::gc_words(&Db::new(default_db_file)?, days)?;
         }
-        Commands::Export { file } => {
-            word_manager::export_words(&Db::new(default_db_file)?, file)?;
+        Commands::Export {
+            file,
+            format,
+            group_by,
+        } => {
+            word_manager::export_words(
+                &Db::new(default_db_file)?,
+                file,
+                *format,
+                *group_by,
+                &calendar,
+            )?;
         }
-        Commands::Import { file } => {
-            word_manager::import_words(&Db::new(default_db_file)?, file)?;
+        Commands::Import { file, format } => {
+            run_with_auto_sync(app_config.as_ref(), false, |db| {
+                word_manager::import_words(db, file, *format, &calendar)
+            })?;
         }
     }
 
@@ -1,3 +1,9 @@
+use anyhow::{Error, Result};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::infra::AppConfig;
+
 pub fn get_last_period_days(current_period_days: u16) -> u16 {
     if current_period_days == 1 {
         return 1;
@@ -13,3 +19,132 @@ pub fn get_next_period_days(current_period_days: u16) -> u16 {
 
     current_period_days * 2
 }
+
+/// Study days in the timezone of the user, starting at the rollover hour instead of midnight,
+/// so that studying past midnight still counts for the day before.
+pub struct StudyCalendar {
+    /// `None` for the local time of the system
+    timezone: Option<Tz>,
+    rollover_hour: u32,
+}
+
+impl StudyCalendar {
+    pub fn new(timezone: Option<&str>, rollover_hour: u32) -> Result<StudyCalendar> {
+        if rollover_hour >= 24 {
+            return Err(Error::msg(format!(
+                "Day rollover hour must be within 0-23, but is {}.",
+                rollover_hour
+            )));
+        }
+        let timezone = match timezone {
+            Some(name) => Some(name.parse::<Tz>().map_err(Error::msg)?),
+            None => None,
+        };
+
+        Ok(StudyCalendar {
+            timezone,
+            rollover_hour,
+        })
+    }
+
+    pub fn from_config(app_config: Option<&AppConfig>) -> Result<StudyCalendar> {
+        StudyCalendar::new(
+            app_config.and_then(|c| c.timezone.as_deref()),
+            app_config
+                .and_then(|c| c.day_rollover_hour)
+                .unwrap_or(AppConfig::DEFAULT_DAY_ROLLOVER_HOUR),
+        )
+    }
+
+    fn to_local(&self, time: DateTime<Utc>) -> NaiveDateTime {
+        match &self.timezone {
+            Some(tz) => time.with_timezone(tz).naive_local(),
+            None => time.with_timezone(&Local).naive_local(),
+        }
+    }
+
+    fn from_local(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        match &self.timezone {
+            Some(tz) => local_to_utc(tz, local),
+            None => local_to_utc(&Local, local),
+        }
+    }
+
+    /// Get the study day the time falls in.
+    pub fn get_study_day(&self, time: DateTime<Utc>) -> NaiveDate {
+        (self.to_local(time) - Duration::hours(self.rollover_hour as i64)).date()
+    }
+
+    pub fn get_day_start(&self, day: NaiveDate) -> DateTime<Utc> {
+        self.from_local(day.and_hms(self.rollover_hour, 0, 0))
+    }
+
+    /// Get when the next visit is due, at the start of the study day `period_days` after today.
+    pub fn get_next_visit(&self, now: DateTime<Utc>, period_days: u16) -> DateTime<Utc> {
+        self.get_day_start(self.get_study_day(now) + Duration::days(period_days as i64))
+    }
+
+    /// Get the start of tomorrow's study day. Words due before it are due today or earlier.
+    pub fn get_due_before(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        self.get_day_start(self.get_study_day(now).succ())
+    }
+
+    pub fn is_due(&self, next_visit: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        next_visit < self.get_due_before(now)
+    }
+}
+
+fn local_to_utc<T: TimeZone>(tz: &T, local: NaiveDateTime) -> DateTime<Utc> {
+    // a time skipped by daylight saving is taken an hour later
+    tz.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(local + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_else(|| DateTime::from_utc(local, Utc))
+}
+
+#[cfg(test)]
+mod revisit_planner_tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_study_calendar() -> Result<()> {
+        let calendar = StudyCalendar::new(Some("Asia/Shanghai"), 4)?;
+        let at = |y, m, d, h| {
+            local_to_utc(
+                &"Asia/Shanghai".parse::<Tz>().unwrap(),
+                NaiveDate::from_ymd(y, m, d).and_hms(h, 0, 0),
+            )
+        };
+
+        // studying at 2 AM counts for the day before
+        assert_eq!(
+            calendar.get_study_day(at(2022, 6, 2, 2)),
+            NaiveDate::from_ymd(2022, 6, 1)
+        );
+        assert_eq!(
+            calendar.get_study_day(at(2022, 6, 2, 4)),
+            NaiveDate::from_ymd(2022, 6, 2)
+        );
+
+        // due the next study day, whatever hour it was studied at
+        for hour in [5, 23] {
+            let next_visit = calendar.get_next_visit(at(2022, 6, 1, hour), 1);
+            assert_eq!(next_visit, at(2022, 6, 2, 4));
+            assert!(!calendar.is_due(next_visit, at(2022, 6, 2, 3)));
+            assert!(calendar.is_due(next_visit, at(2022, 6, 2, 4)));
+        }
+        // visits scheduled late in a day are due from its start
+        assert!(calendar.is_due(at(2022, 6, 2, 22), at(2022, 6, 2, 8)));
+
+        assert!(StudyCalendar::new(Some("Nowhere/Special"), 4).is_err());
+        assert!(StudyCalendar::new(None, 24).is_err());
+
+        Ok(())
+    }
+}
@@ -1,13 +1,60 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fs,
-    io::{self, BufRead, Write},
+    io::{self, BufRead, IsTerminal, Write},
     path::Path,
 };
 
 use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+use clap::ArgEnum;
 
-use crate::infra::{Db, SqlVal, StdResult, Word};
+use crate::{
+    infra::{read_kindle_lookups, Db, SqlVal, StdResult, Word},
+    revisit_planner::StudyCalendar,
+};
+
+#[derive(Clone, Copy, ArgEnum)]
+pub enum ImportFormat {
+    /// JSON object of word names and meanings, as exported
+    Json,
+    /// `vocab.db` of Kindle Vocabulary Builder
+    Kindle,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+pub enum ExportFormat {
+    /// JSON object of word names and meanings, which can be imported again
+    Json,
+    /// Markdown study sheet
+    Md,
+    /// HTML study sheet
+    Html,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+pub enum GroupBy {
+    /// Group words by tag
+    Tag,
+    /// Group words by the day of next revisit
+    Due,
+}
+
+fn parse_word_line(l: &str) -> StdResult<Word, &'static str> {
+    let pair = l.split_once('=');
+    if pair.is_none() {
+        return Err("Unrecognized input. Format: <WORD>=<MEANING1>;<MEANING2>;...;<MEANINGn>");
+    }
+
+    let (name, meanings) = pair.unwrap();
+    let name = name.trim();
+    let meanings = meanings.trim();
+    if name.is_empty() || Word::norm_meanings(meanings).is_empty() {
+        return Err("Name or meaning cannot be empty.");
+    }
+
+    Ok(Word::from_name_and_meanings(name, meanings))
+}
 
 fn read_one_word<T>(lines: &mut T) -> Result<Option<Word>>
 where
@@ -25,51 +72,101 @@ where
             return Ok(None);
         }
 
-        let pair = l.split_once('=');
-        if pair.is_none() {
-            println!("Unrecognized input. Format: <WORD>=<MEANING1>;<MEANING2>;...;<MEANINGn>");
-            println!("Enter empty line to end listing.");
-            continue;
+        match parse_word_line(l) {
+            Ok(word) => break Ok(Some(word)),
+            Err(msg) => {
+                println!("{}", msg);
+                println!("Enter empty line to end listing.");
+            }
         }
+    }
+}
 
-        let (name, meanings) = pair.unwrap();
-        let name = name.trim();
-        let meanings = meanings.trim();
-        if name.is_empty() || meanings.is_empty() {
-            println!("Name or meaning cannot be empty.");
-            println!("Enter empty line to end listing.");
-            continue;
-        }
+/// Make a new word due at the start of the next study day, as if it were visited just now.
+fn schedule_new_word(word: &mut Word, calendar: &StudyCalendar) {
+    word.next_visit = calendar.get_next_visit(word.last_visit, word.period_days);
+}
 
-        let word = Word::from_name_and_meanings(name, meanings);
-        break Ok(Some(word));
+/// Add a word to DB, or merge its meanings into the existing one. Returns whether it is newly added.
+fn add_word_to_db(db: &Db, mut word: Word, calendar: &StudyCalendar) -> Result<bool> {
+    let existing = db.get_by_col("name", SqlVal::Text(&word.name.trim().to_lowercase()))?;
+    if existing.is_none() {
+        schedule_new_word(&mut word, calendar);
+        db.insert_word(&word)?;
+        return Ok(true);
     }
+
+    let mut existing = existing.unwrap();
+    existing.merge_meanings(&word.meanings);
+    db.update_word(&existing)?;
+    Ok(false)
 }
 
-pub fn read_words_to_db(db: &Db) -> Result<usize> {
+pub fn read_words_to_db(db: &Db, calendar: &StudyCalendar) -> Result<usize> {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return read_word_list_to_db(db, stdin.lock(), calendar);
+    }
+
     println!("Enter words, one word per line. Enter empty line to end listing.");
     println!("Format: <WORD>=<MEANING1>;<MEANING2>;...;<MEANINGn>;");
     println!("Example: right=the opposite of left;correct;");
 
     let mut count: usize = 0;
-    let stdin = io::stdin();
     let mut stdin_lines = stdin.lock().lines();
 
     while let Some(word) = read_one_word(&mut stdin_lines)? {
         count += 1;
+        add_word_to_db(db, word, calendar)?;
+    }
+
+    Ok(count)
+}
 
-        let existing = db.get_by_col("name", SqlVal::Text(&word.name.trim().to_lowercase()))?;
-        if existing.is_none() {
-            db.insert_word(&word)?;
+pub fn read_word_file_to_db<T>(db: &Db, file: T, calendar: &StudyCalendar) -> Result<usize>
+where
+    T: AsRef<Path>,
+{
+    println!("Taking words from {}...", file.as_ref().display());
+    let file = fs::File::open(file)?;
+    read_word_list_to_db(db, io::BufReader::new(file), calendar)
+}
+
+/// Take a whole list of words non-interactively. Blank lines and lines starting with `#` are skipped.
+fn read_word_list_to_db<R>(db: &Db, reader: R, calendar: &StudyCalendar) -> Result<usize>
+where
+    R: BufRead,
+{
+    let mut added = 0usize;
+    let mut merged = 0usize;
+    let mut malformed = 0usize;
+    for (i, l) in reader.lines().enumerate() {
+        let l = l?;
+        let l = l.trim();
+        if l.is_empty() || l.starts_with('#') {
             continue;
         }
 
-        let mut existing = existing.unwrap();
-        existing.merge_meanings(&word.meanings);
-        db.update_word(&existing)?;
+        match parse_word_line(l) {
+            Ok(word) => {
+                if add_word_to_db(db, word, calendar)? {
+                    added += 1;
+                } else {
+                    merged += 1;
+                }
+            }
+            Err(msg) => {
+                malformed += 1;
+                println!("Line {}: {}", i + 1, msg);
+            }
+        }
     }
 
-    Ok(count)
+    println!(
+        "{} words added, {} merged, {} malformed lines skipped.",
+        added, merged, malformed
+    );
+    Ok(added + merged)
 }
 
 pub fn change_word(db: &Db, name: &str) -> Result<bool> {
@@ -141,7 +238,47 @@ pub fn clear_words(db: &Db) -> Result<bool> {
     Ok(true)
 }
 
-pub fn import_words<T>(db: &Db, file: T) -> Result<()>
+/// Purge tombstones of words deleted more than `max_age_days` ago.
+/// Tombstones are kept until pushed to remote and synced by every other device known from the
+/// snapshots merged, so that the deletion can still reach them.
+pub fn gc_words(db: &Db, max_age_days: u32) -> Result<usize> {
+    let mut deleted_before = Utc::now() - Duration::days(max_age_days as i64);
+    if let Some(last_push) = db.get_meta("last_push")? {
+        let last_push = DateTime::parse_from_rfc3339(&last_push)?.with_timezone(&Utc);
+        deleted_before = deleted_before.min(last_push);
+    }
+    if let Some(peer_times) = db.get_meta("peer_sync_times")? {
+        let peer_times: BTreeMap<String, i64> = serde_json::from_str(&peer_times)?;
+        if let Some(&synced) = peer_times.values().min() {
+            deleted_before = deleted_before.min(Utc.timestamp(synced, 0));
+        }
+    }
+
+    let count = db.purge_tombstones(deleted_before)?;
+    println!(
+        "{} tombstones of words deleted before {} purged.",
+        count,
+        deleted_before.format("%Y-%m-%d %H:%M UTC")
+    );
+    Ok(count)
+}
+
+pub fn import_words<T>(
+    db: &Db,
+    file: T,
+    format: ImportFormat,
+    calendar: &StudyCalendar,
+) -> Result<()>
+where
+    T: AsRef<Path>,
+{
+    match format {
+        ImportFormat::Json => import_json_words(db, file, calendar),
+        ImportFormat::Kindle => import_kindle_words(db, file, calendar),
+    }
+}
+
+fn import_json_words<T>(db: &Db, file: T, calendar: &StudyCalendar) -> Result<()>
 where
     T: AsRef<Path>,
 {
@@ -150,7 +287,8 @@ where
     let name_meanings_pairs: HashMap<String, String> = serde_json::from_str(&json)?;
     for (n, m) in name_meanings_pairs {
         println!("  {}={}", n, m);
-        let word = Word::from_name_and_meanings(&n, &m);
+        let mut word = Word::from_name_and_meanings(&n, &m);
+        schedule_new_word(&mut word, calendar);
         db.upsert_by_name(&word, false)?;
     }
 
@@ -158,24 +296,434 @@ where
     Ok(())
 }
 
-pub fn export_words<T>(db: &Db, file: T) -> Result<()>
+fn import_kindle_words<T>(db: &Db, file: T, calendar: &StudyCalendar) -> Result<()>
 where
     T: AsRef<Path>,
 {
-    println!("Exporting words to {}...", file.as_ref().display());
-    let mut name_meanings_pairs = HashMap::new();
-    for w in db.get_all_words()? {
-        name_meanings_pairs.insert(w.name, w.meanings);
+    println!(
+        "Importing Kindle lookups from {}...",
+        file.as_ref().display()
+    );
+    let mut words: Vec<Word> = Vec::new();
+    let mut skipped = 0usize;
+    for lookup in read_kindle_lookups(file)? {
+        let name = lookup.word.to_lowercase();
+        let word = match words.iter_mut().find(|w| w.name == name) {
+            Some(w) => w,
+            None => {
+                if db.get_by_col("name", SqlVal::Text(&name))?.is_some() {
+                    skipped += 1;
+                    continue;
+                }
+
+                words.push(Word::from_name_and_meanings(&name, ""));
+                words.last_mut().unwrap()
+            }
+        };
+        word.add_example(&lookup.usage);
+        word.add_tag(&lookup.book_title);
+    }
+
+    for w in words.iter_mut() {
+        println!("  {}", w.name);
+        schedule_new_word(w, calendar);
+        db.insert_word(w)?;
     }
 
-    let json = serde_json::to_string(&name_meanings_pairs)?;
+    println!(
+        "{} words imported, {} lookups of known words skipped.",
+        words.len(),
+        skipped
+    );
+    if !words.is_empty() {
+        println!("Imported words have no meanings yet. Use `wordmem change <word>` to add them before testing.");
+    }
+    Ok(())
+}
+
+pub fn export_words<T>(
+    db: &Db,
+    file: T,
+    format: ExportFormat,
+    group_by: GroupBy,
+    calendar: &StudyCalendar,
+) -> Result<()>
+where
+    T: AsRef<Path>,
+{
+    println!("Exporting words to {}...", file.as_ref().display());
+    let words = db.get_all_words()?;
+    let now = Utc::now();
+    let text = match format {
+        ExportFormat::Json => {
+            let mut name_meanings_pairs = HashMap::new();
+            for w in words {
+                name_meanings_pairs.insert(w.name, w.meanings);
+            }
+
+            serde_json::to_string(&name_meanings_pairs)?
+        }
+        ExportFormat::Md => {
+            render_markdown_sheet(&group_words(&words, group_by, calendar, now), calendar, now)
+        }
+        ExportFormat::Html => {
+            render_html_sheet(&group_words(&words, group_by, calendar, now), calendar, now)
+        }
+    };
+
     let mut file = fs::OpenOptions::new()
         .create(true)
         .truncate(true)
         .write(true)
         .open(file)?;
-    file.write_all(json.as_bytes())?;
+    file.write_all(text.as_bytes())?;
 
     println!("All words exported.");
     Ok(())
 }
+
+/// Group words for a study sheet. A word with multiple tags appears in each of the tag groups.
+fn group_words<'a>(
+    words: &'a [Word],
+    group_by: GroupBy,
+    calendar: &StudyCalendar,
+    now: DateTime<Utc>,
+) -> Vec<(String, Vec<&'a Word>)> {
+    let mut words: Vec<_> = words.iter().collect();
+    words.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let today = calendar.get_study_day(now);
+    let mut groups = BTreeMap::new();
+    for w in words {
+        match group_by {
+            GroupBy::Tag => {
+                let tags: Vec<_> = w.tags.split(';').filter(|t| !t.is_empty()).collect();
+                if tags.is_empty() {
+                    // keep untagged words at the end
+                    groups
+                        .entry((1, "Untagged".to_string()))
+                        .or_insert_with(Vec::new)
+                        .push(w);
+                }
+                for t in tags {
+                    groups
+                        .entry((0, format!("Tag: {}", t)))
+                        .or_insert_with(Vec::new)
+                        .push(w);
+                }
+            }
+            GroupBy::Due => {
+                let day = calendar.get_study_day(w.next_visit).max(today);
+                let title = if day == today {
+                    format!("Due today ({})", day)
+                } else {
+                    format!("Due {}", day)
+                };
+                groups
+                    .entry((day.num_days_from_ce(), title))
+                    .or_insert_with(Vec::new)
+                    .push(w);
+            }
+        }
+    }
+
+    groups.into_iter().map(|((_, t), ws)| (t, ws)).collect()
+}
+
+fn describe_schedule(word: &Word, calendar: &StudyCalendar, now: DateTime<Utc>) -> String {
+    format!(
+        "every {} day(s), last visited {}, next visit {}{}",
+        word.period_days,
+        calendar.get_study_day(word.last_visit),
+        calendar.get_study_day(word.next_visit),
+        if calendar.is_due(word.next_visit, now) {
+            " (due)"
+        } else {
+            ""
+        }
+    )
+}
+
+fn render_markdown_sheet(
+    groups: &[(String, Vec<&Word>)],
+    calendar: &StudyCalendar,
+    now: DateTime<Utc>,
+) -> String {
+    let mut md = format!(
+        "# Study sheet\n\nGenerated at {}.\n",
+        now.format("%Y-%m-%d %H:%M UTC")
+    );
+    for (title, words) in groups {
+        md += &format!("\n## {}\n", escape_markdown(title));
+        for w in words {
+            md += &format!("\n### {}\n\n", escape_markdown(&w.name));
+            if w.meanings.is_empty() {
+                md += "- Meanings: _(none yet)_\n";
+            } else {
+                let meanings: Vec<_> = w.meanings.split(';').map(escape_markdown).collect();
+                md += &format!("- Meanings: {}\n", meanings.join("; "));
+            }
+            if !w.examples.is_empty() {
+                md += "- Examples:\n";
+                for e in w.examples.lines() {
+                    md += &format!("  > {}\n", escape_markdown(e));
+                }
+            }
+            md += &format!("- Schedule: {}\n", describe_schedule(w, calendar, now));
+        }
+    }
+
+    md
+}
+
+fn render_html_sheet(
+    groups: &[(String, Vec<&Word>)],
+    calendar: &StudyCalendar,
+    now: DateTime<Utc>,
+) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Study sheet</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+section.word { break-inside: avoid; margin-bottom: 1em; }
+blockquote { color: #555; margin: 0.2em 1em; }
+.due { color: #b00; }
+</style>
+</head>
+<body>
+<h1>Study sheet</h1>
+",
+    );
+    html += &format!(
+        "<p>Generated at {}.</p>\n",
+        now.format("%Y-%m-%d %H:%M UTC")
+    );
+    for (title, words) in groups {
+        html += &format!("<h2>{}</h2>\n", escape_html(title));
+        for w in words {
+            html += "<section class=\"word\">\n";
+            html += &format!("<h3>{}</h3>\n", escape_html(&w.name));
+            if w.meanings.is_empty() {
+                html += "<p><em>No meanings yet.</em></p>\n";
+            } else {
+                html += "<ul>\n";
+                for m in w.meanings.split(';') {
+                    html += &format!("<li>{}</li>\n", escape_html(m));
+                }
+                html += "</ul>\n";
+            }
+            for e in w.examples.lines() {
+                html += &format!("<blockquote>{}</blockquote>\n", escape_html(e));
+            }
+            html += &format!(
+                "<p{}>Schedule: {}</p>\n",
+                if calendar.is_due(w.next_visit, now) {
+                    " class=\"due\""
+                } else {
+                    ""
+                },
+                escape_html(&describe_schedule(w, calendar, now))
+            );
+            html += "</section>\n";
+        }
+    }
+    html += "</body>\n</html>\n";
+
+    html
+}
+
+/// Escape Markdown metacharacters, so that word text is rendered as it is.
+fn escape_markdown(s: &str) -> String {
+    let mut escaped = String::new();
+    for (i, c) in s.trim().chars().enumerate() {
+        let special = matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '{' | '}' | '[' | ']' | '<' | '>' | '#' | '|' | '~'
+        ) || (i == 0 && matches!(c, '-' | '+'));
+        if special {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod word_manager_tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_read_word_list() -> Result<()> {
+        let db = Db::new_mem()?;
+        let calendar = StudyCalendar::new(Some("UTC"), 4)?;
+        let list = "# greetings\nhello=a greeting\n\nbad line\nworld=the earth\nhello=hi\n";
+        let count = read_word_list_to_db(&db, list.as_bytes(), &calendar)?;
+        assert_eq!(count, 3);
+
+        let hello = db.get_by_col("name", SqlVal::Text("hello"))?.unwrap();
+        assert!(hello.has_meanings("a greeting;hi"));
+        assert!(db.get_by_col("name", SqlVal::Text("world"))?.is_some());
+
+        // new words are due at the start of the next study day
+        assert_eq!(
+            hello.next_visit,
+            calendar.get_day_start(calendar.get_study_day(hello.last_visit).succ())
+        );
+
+        Ok(())
+    }
+
+    fn make_word(name: &str, meanings: &str, tags: &str, next_visit: DateTime<Utc>) -> Word {
+        let mut word = Word::from_name_and_meanings(name, meanings);
+        word.tags = tags.to_string();
+        word.next_visit = next_visit;
+        word
+    }
+
+    #[test]
+    fn test_group_words() -> Result<()> {
+        let calendar = StudyCalendar::new(Some("UTC"), 0)?;
+        let now = "2022-06-10T12:00:00Z".parse::<DateTime<Utc>>()?;
+        let words = vec![
+            make_word("b", "bee", "Book 1;Book 2", now - Duration::days(3)),
+            make_word("a", "ay", "Book 2", now + Duration::days(1)),
+            make_word("c", "see", "", now + Duration::hours(2)),
+        ];
+
+        let names = |groups: Vec<(String, Vec<&Word>)>| -> Vec<(String, Vec<String>)> {
+            groups
+                .into_iter()
+                .map(|(t, ws)| (t, ws.iter().map(|w| w.name.clone()).collect()))
+                .collect()
+        };
+        assert_eq!(
+            names(group_words(&words, GroupBy::Tag, &calendar, now)),
+            vec![
+                ("Tag: Book 1".to_string(), vec!["b".to_string()]),
+                (
+                    "Tag: Book 2".to_string(),
+                    vec!["a".to_string(), "b".to_string()]
+                ),
+                ("Untagged".to_string(), vec!["c".to_string()]),
+            ]
+        );
+        // overdue words are due today
+        assert_eq!(
+            names(group_words(&words, GroupBy::Due, &calendar, now)),
+            vec![
+                (
+                    "Due today (2022-06-10)".to_string(),
+                    vec!["b".to_string(), "c".to_string()]
+                ),
+                ("Due 2022-06-11".to_string(), vec!["a".to_string()]),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_sheets() -> Result<()> {
+        let calendar = StudyCalendar::new(Some("UTC"), 0)?;
+        let now = "2022-06-10T12:00:00Z".parse::<DateTime<Utc>>()?;
+        let mut word = make_word("-a*b_", "<x>;#y", "Tag", now - Duration::days(1));
+        word.add_example("> quoted");
+        let groups = vec![("Tag: Tag".to_string(), vec![&word])];
+
+        let md = render_markdown_sheet(&groups, &calendar, now);
+        assert!(md.contains("\n## Tag: Tag\n"));
+        assert!(md.contains("\n### \\-a\\*b\\_\n"));
+        assert!(md.contains("\\<x\\>") && md.contains("\\#y"));
+        assert!(md.contains("  > \\> quoted\n"));
+        assert!(md.contains("(due)"));
+
+        let html = render_html_sheet(&groups, &calendar, now);
+        assert!(html.contains("<h3>-a*b_</h3>"));
+        assert!(html.contains("<li>&lt;x&gt;</li>"));
+        assert!(html.contains("<blockquote>&gt; quoted</blockquote>"));
+        assert!(html.contains("<p class=\"due\">"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_words() -> Result<()> {
+        let db = Db::new_mem()?;
+        let mut hello = Word::from_name_and_meanings("hello", "a greeting");
+        hello.updated_at = Utc::now() - Duration::days(2);
+        hello.deleted_at = Some(hello.updated_at);
+        db.put_synced_word(&hello)?;
+        let days_ago = |days| (Utc::now() - Duration::days(days)).timestamp();
+
+        // kept until pushed
+        db.set_meta("last_push", &(Utc::now() - Duration::days(3)).to_rfc3339())?;
+        assert_eq!(gc_words(&db, 1)?, 0);
+
+        // and synced by every other device
+        db.set_meta("last_push", &Utc::now().to_rfc3339())?;
+        let peer_times = BTreeMap::from([
+            ("a".to_string(), days_ago(0)),
+            ("b".to_string(), days_ago(3)),
+        ]);
+        db.set_meta("peer_sync_times", &serde_json::to_string(&peer_times)?)?;
+        assert_eq!(gc_words(&db, 1)?, 0);
+
+        let peer_times = BTreeMap::from([("a".to_string(), days_ago(0))]);
+        db.set_meta("peer_sync_times", &serde_json::to_string(&peer_times)?)?;
+        assert_eq!(gc_words(&db, 1)?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_kindle_words() -> Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        let conn = sqlite::Connection::open(file.path())?;
+        conn.execute(
+            "CREATE TABLE WORDS (id TEXT PRIMARY KEY NOT NULL, word TEXT, stem TEXT, lang TEXT,
+                category INTEGER DEFAULT 0, timestamp INTEGER DEFAULT 0, profileid TEXT);
+            CREATE TABLE LOOKUPS (id TEXT PRIMARY KEY NOT NULL, word_key TEXT, book_key TEXT,
+                dict_key TEXT, pos TEXT, usage TEXT, timestamp INTEGER DEFAULT 0);
+            CREATE TABLE BOOK_INFO (id TEXT PRIMARY KEY NOT NULL, asin TEXT, guid TEXT, lang TEXT,
+                title TEXT, authors TEXT);
+            INSERT INTO WORDS VALUES ('en:running', 'running', 'run', 'en', 0, 1, '');
+            INSERT INTO WORDS VALUES ('en:hello', 'hello', 'hello', 'en', 0, 2, '');
+            INSERT INTO BOOK_INFO VALUES ('b1', 'asin1', 'guid1', 'en', 'A Book', 'Someone');
+            INSERT INTO BOOK_INFO VALUES ('b2', 'asin2', 'guid2', 'en', 'Another Book', 'Someone');
+            INSERT INTO LOOKUPS VALUES ('l1', 'en:running', 'b1', '', '', 'He was running.', 3);
+            INSERT INTO LOOKUPS VALUES ('l2', 'en:running', 'b2', '', '', 'She runs.', 4);
+            INSERT INTO LOOKUPS VALUES ('l3', 'en:hello', 'b1', '', '', 'Hello there.', 5);",
+        )?;
+
+        let db = Db::new_mem()?;
+        db.insert_word(&Word::from_name_and_meanings("hello", "a greeting"))?;
+        import_kindle_words(&db, file.path(), &StudyCalendar::new(Some("UTC"), 4)?)?;
+
+        let run = db.get_by_col("name", SqlVal::Text("run"))?.unwrap();
+        assert_eq!(run.meanings, "");
+        assert_eq!(run.examples, "He was running.\nShe runs.");
+        assert_eq!(run.tags, "A Book;Another Book");
+
+        // known words are left as they are
+        let hello = db.get_by_col("name", SqlVal::Text("hello"))?.unwrap();
+        assert!(hello.has_meanings("a greeting"));
+        assert_eq!(hello.examples, "");
+        assert_eq!(hello.tags, "");
+
+        Ok(())
+    }
+}
@@ -1,12 +1,15 @@
 use std::io::{self, BufRead};
 
 use anyhow::Result;
-use chrono::{Duration, Utc};
+use chrono::Utc;
 
-use crate::{infra::Db, revisit_planner};
+use crate::{
+    infra::Db,
+    revisit_planner::{self, StudyCalendar},
+};
 
-fn test_one_word(db: &Db) -> Result<bool> {
-    let word = db.get_one_word_to_test()?;
+fn test_one_word(db: &Db, calendar: &StudyCalendar) -> Result<bool> {
+    let word = db.get_one_word_to_test(calendar.get_due_before(Utc::now()))?;
     if word.is_none() {
         println!("No word planned to test at now.");
         return Ok(false);
@@ -75,16 +78,16 @@ fn test_one_word(db: &Db) -> Result<bool> {
     } else {
         revisit_planner::get_last_period_days(word.period_days)
     };
-    word.next_visit = now + Duration::days(word.period_days as i64);
+    word.next_visit = calendar.get_next_visit(now, word.period_days);
     db.update_word(&word)?;
 
     Ok(true)
 }
 
-pub fn do_tests(db: &Db) -> Result<usize> {
+pub fn do_tests(db: &Db, calendar: &StudyCalendar) -> Result<usize> {
     let mut count = 0usize;
     println!("Note: Enter empty line to abort test.");
-    while test_one_word(db)? {
+    while test_one_word(db, calendar)? {
         count += 1;
     }
     Ok(count)